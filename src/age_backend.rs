@@ -0,0 +1,183 @@
+use age;
+use age::secrecy::SecretString;
+use git2::Repository;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use crypto::CryptoBackend;
+use error::GitCredError;
+use error::Result;
+use git_config;
+
+const AGE_HEADER: &'static [u8] = b"age-encryption.org/";
+const PASSPHRASE_ENV_NAME: &'static str = "GIT_CRED_PASSPHRASE";
+
+/* Backend using age, recipients are age public keys ("age1...") or ssh public keys
+ * ("ssh-ed25519 ..." and "ssh-rsa ..."). Files are decrypted with the identity file set in
+ * creds.identity, or with ~/.ssh/id_ed25519 and ~/.ssh/id_rsa if unset.
+ * Passphrase protected ssh keys are unlocked with the GIT_CRED_PASSPHRASE environment variable */
+pub struct AgeBackend {
+    identity_files: Vec<PathBuf>,
+}
+
+impl AgeBackend {
+    pub fn new(repo: &Repository) -> Result<AgeBackend> {
+        let identity_files = match git_config::get_identity_location(repo)? {
+            Some(location) => vec![location],
+            None => {
+                let ssh_dir = Path::new(&env::var("HOME").unwrap_or(String::new())).join(".ssh");
+                vec![ssh_dir.join("id_ed25519"), ssh_dir.join("id_rsa")]
+                    .into_iter()
+                    .filter(|path| path.is_file())
+                    .collect()
+            }
+        };
+        Ok(AgeBackend { identity_files })
+    }
+
+    fn identities(&self) -> Result<Vec<Box<dyn age::Identity>>> {
+        let mut identities: Vec<Box<dyn age::Identity>> = Vec::new();
+        for path in &self.identity_files {
+            let contents = fs::read(path)?;
+            if contents.starts_with(b"-----BEGIN") {
+                let filename = path.to_string_lossy().into_owned();
+                let identity = age::ssh::Identity::from_buffer(&contents[..], Some(filename))?;
+                identities.push(Box::new(identity.with_callbacks(PassphraseCallbacks)));
+            } else {
+                let identity_file = age::IdentityFile::from_buffer(&contents[..])?;
+                let mut file_identities = identity_file.into_identities()
+                    .map_err(|err| age_error(&format!("read identity {}", path.display()), err))?;
+                identities.append(&mut file_identities);
+            }
+        }
+        Ok(identities)
+    }
+}
+
+impl CryptoBackend for AgeBackend {
+    fn decrypt(&self, path: &Path) -> Result<Vec<u8>> {
+        let operation = format!("decrypt {}", path.display());
+        let identities = self.identities()?;
+        if identities.is_empty() {
+            return Err(GitCredError::Crypto { operation, message: "no age identity or ssh key found".to_string() });
+        }
+
+        let contents = fs::read(path)?;
+        let decryptor = age::Decryptor::new_buffered(&contents[..])
+            .map_err(|err| age_error(&operation, err))?;
+        let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))
+            .map_err(|err| age_error(&operation, err))?;
+
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn encrypt(&self, path: &Path, contents: &[u8], recipients: &Vec<&str>) -> Result<()> {
+        let operation = format!("encrypt {}", path.display());
+        let mut parsed_recipients = Vec::new();
+        for recipient in recipients {
+            match parse_recipient(recipient) {
+                Some(parsed) => parsed_recipients.push(parsed),
+                None => return Err(GitCredError::UnresolvableRecipient(recipient.to_string())),
+            }
+        }
+
+        let encryptor = age::Encryptor::with_recipients(parsed_recipients.iter().map(|recipient| recipient.as_ref() as &dyn age::Recipient))
+            .map_err(|err| age_error(&operation, err))?;
+        let mut ciphertext = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+        writer.write_all(contents)?;
+        writer.finish()?;
+
+        fs::write(path, ciphertext)?;
+        Ok(())
+    }
+
+    fn has_key(&self, id: &str) -> Result<bool> {
+        Ok(is_recipient(id))
+    }
+
+    fn get_key_id(&self, id: &str) -> Result<String> {
+        self.export_key(id)
+    }
+
+    /* age recipients aren't identified by key ids */
+    fn get_key_ids(&self, _: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_secret_key_ids(&self) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+
+    fn import_key(&self, key_contents: &str) -> Result<String> {
+        self.export_key(key_contents.trim())
+    }
+
+    fn inspect_key(&self, key_contents: &str) -> Result<(String, Vec<String>)> {
+        Ok((self.export_key(key_contents.trim())?, Vec::new()))
+    }
+
+    /* age keys are their own public key */
+    fn export_key(&self, id: &str) -> Result<String> {
+        if is_recipient(id) {
+            Ok(id.to_string())
+        } else {
+            Err(GitCredError::UnresolvableRecipient(id.to_string()))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PassphraseCallbacks;
+
+impl age::Callbacks for PassphraseCallbacks {
+    fn display_message(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+
+    fn confirm(&self, _: &str, _: &str, _: Option<&str>) -> Option<bool> {
+        None
+    }
+
+    fn request_public_string(&self, _: &str) -> Option<String> {
+        None
+    }
+
+    fn request_passphrase(&self, _: &str) -> Option<SecretString> {
+        env::var(PASSPHRASE_ENV_NAME).ok().map(SecretString::from)
+    }
+}
+
+/* Returns whether the id is an age or ssh public key rather than a name to resolve */
+pub fn is_recipient(id: &str) -> bool {
+    parse_recipient(id).is_some()
+}
+
+pub fn is_age_file(path: &Path) -> Result<bool> {
+    let mut header = [0; 19];
+    let mut file = fs::File::open(path)?;
+    let read = file.read(&mut header)?;
+    Ok(&header[..read] == AGE_HEADER)
+}
+
+fn parse_recipient(id: &str) -> Option<Box<dyn age::Recipient + Send>> {
+    if let Ok(recipient) = id.parse::<age::x25519::Recipient>() {
+        return Some(Box::new(recipient));
+    }
+    if let Ok(recipient) = id.parse::<age::ssh::Recipient>() {
+        return Some(Box::new(recipient));
+    }
+    None
+}
+
+fn age_error<E: ToString>(operation: &str, err: E) -> GitCredError {
+    GitCredError::Crypto {
+        operation: operation.to_string(),
+        message: err.to_string(),
+    }
+}
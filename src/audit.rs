@@ -0,0 +1,155 @@
+use git2::Index;
+use git2::Repository;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::io;
+use crypto;
+use crypto::BackendKind;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use gpg_id;
+use recipients::RecipientChecker;
+use store::Store;
+
+const HOOK_NAME: &'static str = "pre-commit";
+const HOOK_COMMAND: &'static str = "git cred check-staged";
+const GPG_ID_FILE_NAME: &'static str = ".gpg_id";
+const BACKEND_FILE_NAME: &'static str = ".backend";
+
+/// Something wrong with an encrypted file of the store
+#[derive(Debug)]
+pub enum Problem {
+    /// The file isn't an encrypted OpenPGP message (or age file, in age folders)
+    NotEncrypted,
+    /// The file isn't encrypted for exactly the recipients of its folder. Missing recipients are
+    /// gpg ids from `.gpg_id` files, extra recipients are the key ids found in the file
+    WrongRecipients { missing: Vec<String>, extra: Vec<String> },
+    /// The age file has a different number of recipients than its folder,
+    /// age recipients can't be told apart without decrypting
+    WrongRecipientCount { expected: usize, found: usize },
+    /// The gpg ids of the folder of the file can't be resolved, so its recipients can't be checked
+    Unresolvable(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::NotEncrypted => write!(f, "not encrypted"),
+            Problem::WrongRecipients { ref missing, ref extra } => {
+                let mut parts = Vec::new();
+                if !missing.is_empty() {
+                    parts.push(format!("missing recipients: {}", missing.join(", ")));
+                }
+                if !extra.is_empty() {
+                    parts.push(format!("extra recipients: {}", extra.join(", ")));
+                }
+                write!(f, "{}", parts.join("; "))
+            }
+            Problem::WrongRecipientCount { expected, found } => {
+                write!(f, "encrypted for {} recipient(s) instead of {}", found, expected)
+            }
+            Problem::Unresolvable(ref reason) => write!(f, "gpg ids can't be resolved: {}", reason),
+        }
+    }
+}
+
+/// Checks every staged file of the store (except dot-files such as `.gpg_id` and `.keys`)
+/// is encrypted for exactly the recipients of its folder, reading the staged contents, and the
+/// staged `.gpg_id` and `.backend` files, from the index. Returns the problems found, with the
+/// path of each file in the store.
+pub fn check_staged(store: &Store) -> Result<Vec<(PathBuf, Problem)>> {
+    let repo = store.repo();
+    let relative_store = git_utils::get_relative_credentials_dir(repo)?;
+    let index = repo.index()?;
+    let mut checker = RecipientChecker::new(store.repo());
+    let mut problems = Vec::new();
+    for entry in index.iter() {
+        let path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+        let store_path = match path.strip_prefix(&relative_store) {
+            Ok(store_path) => store_path.to_path_buf(),
+            Err(_) => continue,
+        };
+        if is_hidden(&store_path) {
+            continue;
+        }
+
+        let folder = store_path.parent().unwrap_or(Path::new(""));
+        let gpg_ids = match read_staged(repo, &index, &relative_store, folder, GPG_ID_FILE_NAME)? {
+            Some(contents) => gpg_id::parse(&contents),
+            None => Vec::new(),
+        };
+        let backend_kind = match read_staged(repo, &index, &relative_store, folder, BACKEND_FILE_NAME)? {
+            Some(name) => BackendKind::from_name(name.trim())?,
+            None => crypto::get_backend_kind(repo)?,
+        };
+        let blob = repo.find_blob(entry.id)?;
+        if let Some(problem) = checker.check_for(&store_path, backend_kind, &gpg_ids, blob.content())? {
+            problems.push((store_path, problem));
+        }
+    }
+    Ok(problems)
+}
+
+/// Checks every secret under `folder` (use an empty path for the whole store) is encrypted for
+/// exactly the recipients of its folder, without decrypting it. Returns the problems found,
+/// with the path of each file in the store.
+pub fn verify<P: AsRef<Path>>(store: &Store, folder: P) -> Result<Vec<(PathBuf, Problem)>> {
+    let credentials_dir = store.path()?;
+    let mut checker = RecipientChecker::new(store.repo());
+    let mut problems = Vec::new();
+    for path in store.list_folder(folder)? {
+        let contents = fs::read(credentials_dir.join(&path))?;
+        if let Some(problem) = checker.check(&path, &contents)? {
+            problems.push((path, problem));
+        }
+    }
+    Ok(problems)
+}
+
+/// Installs a pre-commit hook running `git cred check-staged`
+pub fn install_hook(store: &Store) -> Result<()> {
+    let repo = store.repo();
+    let hooks_dir = match repo.config()?.get_path("core.hooksPath") {
+        Ok(path) => git_utils::get_top_level_directory(repo).join(path),
+        Err(_) => repo.path().join("hooks"),
+    };
+    let hook_path = hooks_dir.join(HOOK_NAME);
+
+    if hook_path.is_file() {
+        if fs::read_to_string(&hook_path)?.contains(HOOK_COMMAND) {
+            return Ok(());
+        }
+        return Err(GitCredError::Io(io::Error::new(io::ErrorKind::AlreadyExists,
+            format!("{} already exists, add '{}' to it instead", hook_path.display(), HOOK_COMMAND))));
+    }
+
+    fs::create_dir_all(&hooks_dir)?;
+    fs::write(&hook_path, format!("#!/bin/sh\nexec {}\n", HOOK_COMMAND))?;
+    fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/* Returns the staged contents of the closest `file_name` file in the folder of the store or one of its parents */
+fn read_staged(repo: &Repository, index: &Index, relative_store: &Path, folder: &Path, file_name: &str) -> Result<Option<String>> {
+    let mut current = Some(folder);
+    while let Some(folder) = current {
+        if let Some(entry) = index.get_path(&relative_store.join(folder).join(file_name), 0) {
+            let blob = repo.find_blob(entry.id)?;
+            return Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()));
+        }
+        current = folder.parent();
+    }
+    Ok(None)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with("."),
+        _ => false,
+    })
+}
@@ -0,0 +1,33 @@
+extern crate git2;
+extern crate git_cred;
+
+use git2::Repository;
+use git_cred::Store;
+
+/* Entry point for `git config credential.helper cred`, which makes git run
+ * `git credential-cred <action>`. Equivalent to `git cred credential <action>` */
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Please provide a credential helper action: get, store or erase");
+        std::process::exit(2);
+    }
+
+    let repo = match Repository::discover(".") {
+        Ok(repo) => repo,
+        Err(err) => {
+            eprintln!("Current directory isn't a git repo!");
+            std::process::exit(git_cred::GitCredError::Git(err).exit_code());
+        }
+    };
+
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    /* only storing a credential creates the store, git runs the helper in every repo */
+    let store = Store::open_without_creating(&repo);
+    let res = git_cred::credential::run(&store, &args[1], stdin.lock(), stdout.lock());
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
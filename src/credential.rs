@@ -0,0 +1,257 @@
+use std::fs;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use error::Result;
+use git_config;
+use store::Store;
+
+const DEFAULT_HELPER_FOLDER: &'static str = "git-credentials";
+
+/* The only attributes that get encrypted, the rest either identifies the credential or only
+ * matters for the request git is making (capability[], state[], wwwauth[], ...) */
+const SECRET_ATTRIBUTES: [&'static str; 3] = ["password", "password_expiry_utc", "oauth_refresh_token"];
+
+/// Implements git's credential helper protocol (`get`, `store` and `erase`), reading the
+/// credential description from `input` and writing the answer to `output`.
+///
+/// Credentials are kept at `<folder>/<protocol>/<host>[/<path>]/<username>` in the store,
+/// where `<folder>` is the `creds.helperfolder` git config (`git-credentials` by default).
+pub fn run<R: BufRead, W: Write>(store: &Store, action: &str, input: R, mut output: W) -> Result<()> {
+    let credential = read_credential(input)?;
+    let folder = match get_entry_folder(store, &credential)? {
+        Some(folder) => folder,
+        None => return Ok(()),
+    };
+
+    match action {
+        "get" => get(store, &folder, &credential, &mut output),
+        "store" => store_credential(store, &folder, &credential),
+        "erase" => erase(store, &folder, &credential),
+        /* helpers should ignore actions they don't know about */
+        _ => Ok(()),
+    }
+}
+
+fn get<W: Write>(store: &Store, folder: &Path, credential: &[(String, String)], output: &mut W) -> Result<()> {
+    let username = match get_attribute(credential, "username") {
+        Some(username) => Some(username.to_string()),
+        None => first_username(store, folder)?,
+    };
+    let username = match username {
+        Some(username) => username,
+        None => return Ok(()),
+    };
+
+    let entry = folder.join(escape(&username));
+    if !store.path()?.join(&entry).is_file() {
+        return Ok(());
+    }
+
+    /* git keeps going with the other helpers or prompts if we don't answer */
+    let secret = match store.decrypt(&entry) {
+        Ok(secret) => secret,
+        Err(err) => {
+            eprintln!("git-cred: unable to decrypt {}: {}", entry.display(), err);
+            return Ok(());
+        }
+    };
+
+    /* entries stored by older versions can contain other attributes */
+    writeln!(output, "username={}", username)?;
+    for (key, value) in read_credential(&secret[..])? {
+        if SECRET_ATTRIBUTES.contains(&key.as_str()) {
+            writeln!(output, "{}={}", key, value)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn store_credential(store: &Store, folder: &Path, credential: &[(String, String)]) -> Result<()> {
+    let username = match get_attribute(credential, "username") {
+        Some(username) => username,
+        None => return Ok(()),
+    };
+    if get_attribute(credential, "password").is_none() {
+        return Ok(());
+    }
+
+    let mut secret = String::new();
+    for (key, value) in credential {
+        if SECRET_ATTRIBUTES.contains(&key.as_str()) {
+            secret += &format!("{}={}\n", key, value);
+        }
+    }
+
+    /* git stores the credential after every successful use, don't rewrite unchanged entries */
+    let entry = folder.join(escape(username));
+    if store.path()?.join(&entry).is_file() {
+        if let Ok(existing) = store.decrypt(&entry) {
+            if existing == secret.as_bytes() {
+                return Ok(());
+            }
+        }
+    }
+
+    store.encrypt(&entry, secret)
+}
+
+fn erase(store: &Store, folder: &Path, credential: &[(String, String)]) -> Result<()> {
+    let username = match get_attribute(credential, "username") {
+        Some(username) => username,
+        None => return Ok(()),
+    };
+
+    let entry = folder.join(escape(username));
+    if store.path()?.join(&entry).is_file() {
+        store.remove(&entry)?;
+    }
+    Ok(())
+}
+
+fn read_credential<R: BufRead>(input: R) -> Result<Vec<(String, String)>> {
+    let mut credential = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().to_string();
+        if let Some(value) = parts.next() {
+            credential.push((key, value.to_string()));
+        }
+    }
+    Ok(credential)
+}
+
+fn get_attribute<'a>(credential: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    credential.iter()
+        .find(|(attribute, _)| attribute == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn get_entry_folder(store: &Store, credential: &[(String, String)]) -> Result<Option<PathBuf>> {
+    let protocol = get_attribute(credential, "protocol");
+    let host = get_attribute(credential, "host");
+    if protocol.is_none() || host.is_none() {
+        return Ok(None);
+    }
+
+    let mut folder = match git_config::get_helper_folder(store.repo())? {
+        Some(folder) => folder,
+        None => PathBuf::from(DEFAULT_HELPER_FOLDER),
+    };
+    folder.push(escape(protocol.unwrap()));
+    folder.push(escape(host.unwrap()));
+    if let Some(path) = get_attribute(credential, "path") {
+        for component in path.split('/') {
+            if !component.is_empty() && !component.starts_with(".") {
+                folder.push(component);
+            }
+        }
+    }
+    Ok(Some(folder))
+}
+
+/* First username stored for the folder, for when git doesn't know the username yet */
+fn first_username(store: &Store, folder: &Path) -> Result<Option<String>> {
+    let path = store.path()?.join(folder);
+    if !path.is_dir() {
+        return Ok(None);
+    }
+
+    let mut usernames = Vec::new();
+    for file in fs::read_dir(path)? {
+        let file_res = file?;
+        let file_name = file_res.file_name().to_string_lossy().into_owned();
+        if !file_name.starts_with(".") && file_res.file_type()?.is_file() {
+            usernames.push(unescape(&file_name));
+        }
+    }
+    usernames.sort();
+    Ok(usernames.into_iter().next())
+}
+
+/* Makes the value usable as a single file name */
+fn escape(value: &str) -> String {
+    let escaped = value.replace("%", "%25").replace("/", "%2F");
+    if escaped.starts_with(".") {
+        return escaped.replacen(".", "%2E", 1);
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("%2E", ".").replace("%2F", "/").replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use test_utils::TestRepo;
+    use super::*;
+
+    fn run_action(test_repo: &TestRepo, action: &str, input: &str) -> String {
+        let mut output = Vec::new();
+        run(&test_repo.store(), action, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn reads_credential_until_blank_line() {
+        let input = "protocol=https\nhost=example.com\nwwwauth[]=Basic realm=\"a=b\"\nnot an attribute\n\nusername=after\n";
+        let credential = read_credential(input.as_bytes()).unwrap();
+        assert_eq!(credential, vec![
+            ("protocol".to_string(), "https".to_string()),
+            ("host".to_string(), "example.com".to_string()),
+            ("wwwauth[]".to_string(), "Basic realm=\"a=b\"".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn escapes_file_names() {
+        assert_eq!(escape("user@example.com"), "user@example.com");
+        assert_eq!(escape("a/b%c"), "a%2Fb%25c");
+        assert_eq!(escape(".hidden.name"), "%2Ehidden.name");
+        assert_eq!(escape("%2F"), "%252F");
+        for value in &["a/b%c", ".hidden", "%2E", "%252F", "..", "plain"] {
+            assert_eq!(unescape(&escape(value)), *value);
+        }
+    }
+
+    #[test]
+    fn skips_dot_components_of_paths() {
+        let test_repo = TestRepo::new();
+        let credential = read_credential("protocol=https\nhost=example.com\npath=org/../.git/repo.git\n".as_bytes()).unwrap();
+        let folder = get_entry_folder(&test_repo.store(), &credential).unwrap();
+        assert_eq!(folder, Some(Path::new("git-credentials/https/example.com/org/repo.git").to_path_buf()));
+    }
+
+    #[test]
+    fn stores_only_secret_attributes() {
+        let test_repo = TestRepo::new();
+        let request = "capability[]=authtype\nprotocol=https\nhost=example.com\nusername=me\npassword=hunter2\n\
+            password_expiry_utc=4102444800\nwwwauth[]=Basic realm=\"x\"\nstate[]=helper:1\n\n";
+        run_action(&test_repo, "store", request);
+
+        let stored = test_repo.store().decrypt_string("git-credentials/https/example.com/me").unwrap();
+        assert_eq!(stored, "password=hunter2\npassword_expiry_utc=4102444800\n");
+
+        let answer = run_action(&test_repo, "get", "protocol=https\nhost=example.com\n\n");
+        assert_eq!(answer, "username=me\npassword=hunter2\npassword_expiry_utc=4102444800\n");
+
+        run_action(&test_repo, "erase", "protocol=https\nhost=example.com\nusername=me\n\n");
+        assert_eq!(run_action(&test_repo, "get", "protocol=https\nhost=example.com\n\n"), "");
+    }
+
+    #[test]
+    fn reading_doesnt_create_the_store() {
+        let test_repo = TestRepo::without_store();
+        assert_eq!(run_action(&test_repo, "get", "protocol=https\nhost=example.com\n\n"), "");
+        run_action(&test_repo, "erase", "protocol=https\nhost=example.com\nusername=me\n\n");
+        assert!(!test_repo.exists(".credential-store"));
+    }
+}
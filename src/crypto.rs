@@ -0,0 +1,112 @@
+use git2::Repository;
+use std::path::Path;
+use age_backend;
+use age_backend::AgeBackend;
+use error::GitCredError;
+use error::Result;
+use git_config;
+use gpg::GpgBackend;
+#[cfg(feature = "native-pgp")]
+use native::NativeBackend;
+
+/* Everything git-cred needs from an encryption implementation.
+ * Key ids returned by a backend are what gets written in the lock files */
+pub trait CryptoBackend {
+    fn decrypt(&self, path: &Path) -> Result<Vec<u8>>;
+
+    fn encrypt(&self, path: &Path, contents: &[u8], recipients: &Vec<&str>) -> Result<()>;
+
+    /* Returns whether a public key for `id` (email, key id or fingerprint) is known */
+    fn has_key(&self, id: &str) -> Result<bool>;
+
+    fn get_key_id(&self, id: &str) -> Result<String>;
+
+    /* Returns the long key ids of the key for `id` and of its subkeys, as written in
+     * the recipient packets of messages encrypted to it */
+    fn get_key_ids(&self, id: &str) -> Result<Vec<String>>;
+
+    /* Returns the long key ids of the secret keys (and subkeys) available for decryption,
+     * or None if the backend can't tell which messages it can decrypt without trying */
+    fn get_secret_key_ids(&self) -> Result<Option<Vec<String>>>;
+
+    /* Imports an armored public key, returns the key id of the imported key */
+    fn import_key(&self, key_contents: &str) -> Result<String>;
+
+    /* Returns the key id import_key would return for an armored public key and the long key ids
+     * of the key and its subkeys, without importing it */
+    fn inspect_key(&self, key_contents: &str) -> Result<(String, Vec<String>)>;
+
+    /* Returns the armored public key for `id` */
+    fn export_key(&self, id: &str) -> Result<String>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    Gpg,
+    #[cfg(feature = "native-pgp")]
+    Native,
+    Age,
+}
+
+impl BackendKind {
+    pub fn from_name(name: &str) -> Result<BackendKind> {
+        match name {
+            "gpg" => Ok(BackendKind::Gpg),
+            #[cfg(feature = "native-pgp")]
+            "native" => Ok(BackendKind::Native),
+            "age" => Ok(BackendKind::Age),
+            _ => Err(GitCredError::UnknownBackend(name.to_string())),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BackendKind::Gpg => "gpg",
+            #[cfg(feature = "native-pgp")]
+            BackendKind::Native => "native",
+            BackendKind::Age => "age",
+        }
+    }
+
+    pub fn create(&self, repo: &Repository) -> Result<Box<dyn CryptoBackend>> {
+        match *self {
+            BackendKind::Gpg => Ok(Box::new(GpgBackend)),
+            #[cfg(feature = "native-pgp")]
+            BackendKind::Native => Ok(Box::new(NativeBackend::new(repo)?)),
+            BackendKind::Age => Ok(Box::new(AgeBackend::new(repo)?)),
+        }
+    }
+}
+
+/* Returns the backend of the whole store, selected by the creds.backend git config, gpg by default.
+ * Folders can override it with a .backend file */
+pub fn get_backend_kind(repo: &Repository) -> Result<BackendKind> {
+    match git_config::get_backend(repo)? {
+        Some(name) => BackendKind::from_name(&name),
+        None => Ok(BackendKind::Gpg),
+    }
+}
+
+/* Returns the OpenPGP backend, used for gpg ids and files that aren't age encrypted */
+pub fn get_pgp_backend(repo: &Repository) -> Result<Box<dyn CryptoBackend>> {
+    get_pgp_backend_kind(repo)?.create(repo)
+}
+
+pub fn get_pgp_backend_kind(repo: &Repository) -> Result<BackendKind> {
+    match get_backend_kind(repo)? {
+        BackendKind::Age => Ok(BackendKind::Gpg),
+        kind => Ok(kind),
+    }
+}
+
+/* Returns the backend able to decrypt the file at `path`, based on the file's header */
+pub fn get_backend_for_ciphertext(repo: &Repository, path: &Path) -> Result<Box<dyn CryptoBackend>> {
+    get_backend_kind_for_ciphertext(repo, path)?.create(repo)
+}
+
+pub fn get_backend_kind_for_ciphertext(repo: &Repository, path: &Path) -> Result<BackendKind> {
+    if age_backend::is_age_file(path)? {
+        return Ok(BackendKind::Age);
+    }
+    get_pgp_backend_kind(repo)
+}
@@ -1,17 +1,18 @@
-use gpg;
+use crypto;
 use git_utils;
 use git2::Repository;
+use std::io;
 use std::path::Path;
-use util;
+use error::GitCredError;
+use error::Result;
 
 
-pub fn decrypt(repo: &Repository, path: &Path) -> String {
-    let credential_path = git_utils::get_credentials_dir(repo);
+pub fn decrypt(repo: &Repository, path: &Path) -> Result<Vec<u8>> {
+    let credential_path = git_utils::get_credentials_location(repo)?;
     let path_to_file = credential_path.join(path);
-
-    let contents = gpg::decrypt(path_to_file);
-    if contents.is_err() {
-        util::error_out(&format!("Decryption failed for file: {}", path.display()));
+    if !path_to_file.is_file() {
+        return Err(GitCredError::Io(io::Error::new(io::ErrorKind::NotFound, format!("No such file in credential store: {}", path.display()))));
     }
-    return contents.unwrap();
-}
\ No newline at end of file
+
+    crypto::get_backend_for_ciphertext(repo, &path_to_file)?.decrypt(&path_to_file)
+}
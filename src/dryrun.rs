@@ -0,0 +1,248 @@
+use git2::Repository;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use crypto;
+use crypto::BackendKind;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_config;
+use git_utils;
+use recipients::RecipientChecker;
+use reencrypt;
+use reencrypt::ReencryptOptions;
+use resolver;
+use resolver::KeySource;
+
+/* The gpg ids and backend init would give a folder */
+struct Change {
+    folder: PathBuf,
+    gpg_ids: Vec<String>,
+    backend: Option<BackendKind>,
+}
+
+/* How a gpg id resolves: its key ids (or age recipients), or why it doesn't */
+type Resolution = ::std::result::Result<Vec<String>, String>;
+
+/* Prints what init would change, without touching the store or the keyring */
+pub fn init(repo: &Repository, folder: &Path, recipients: Vec<String>, backend: Option<&str>, options: &ReencryptOptions) -> Result<()> {
+    let gpg_ids = if recipients.is_empty() {
+        git_config::get_email(repo)?.into_iter().collect()
+    } else {
+        recipients
+    };
+    let backend = match backend {
+        Some(name) => Some(BackendKind::from_name(name)?),
+        None => None,
+    };
+    let change = Change { folder: folder.to_path_buf(), gpg_ids, backend };
+    let files = if git_utils::get_credentials_location(repo)?.join(folder).is_dir() {
+        reencrypt::list_files(repo, &[folder.to_path_buf()])?
+    } else {
+        Vec::new()
+    };
+    run(repo, files, Some(&change), options)
+}
+
+/* Prints what reencrypt would change, without touching the store or the keyring */
+pub fn reencrypt(repo: &Repository, paths: &[PathBuf], options: &ReencryptOptions) -> Result<()> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    if !credentials_dir.is_dir() {
+        return Err(GitCredError::NoStore(credentials_dir));
+    }
+    run(repo, reencrypt::list_files(repo, paths)?, None, options)
+}
+
+fn run(repo: &Repository, files: Vec<PathBuf>, change: Option<&Change>, options: &ReencryptOptions) -> Result<()> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut folders: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    if let Some(change) = change {
+        folders.insert(change.folder.clone(), Vec::new());
+    }
+    for file in files {
+        let folder = file.parent().map(|parent| parent.to_path_buf()).unwrap_or(PathBuf::new());
+        folders.entry(folder).or_default().push(file);
+    }
+
+    /* the store is created with the first change, until then no folder has gpg ids */
+    let store_exists = credentials_dir.is_dir();
+    println!("Dry run, nothing is changed");
+    if !store_exists {
+        println!("The credential store would be created at {}", git_utils::get_relative_credentials_dir(repo)?.display());
+    }
+    println!("Folders:");
+    let mut targets: BTreeMap<PathBuf, (BackendKind, Vec<String>)> = BTreeMap::new();
+    for folder in folders.keys() {
+        let old_gpg_ids = if store_exists { encrypt::get_gpg_ids_for_folder(repo, folder)? } else { Vec::new() };
+        let old_backend = encrypt::get_backend_kind_for_folder(repo, folder)?;
+        let mut new_gpg_ids = old_gpg_ids.clone();
+        let mut new_backend = old_backend;
+        if let Some(change) = change {
+            if is_changed(&credentials_dir, &change.folder, folder, ".gpg_id") {
+                new_gpg_ids = change.gpg_ids.clone();
+            }
+            if let Some(backend) = change.backend {
+                if is_changed(&credentials_dir, &change.folder, folder, ".backend") {
+                    new_backend = backend;
+                }
+            }
+        }
+
+        let mut line = format!("    {} ({}): {}", display_folder(folder), old_backend.name(), old_gpg_ids.join(", "));
+        if new_backend != old_backend || new_gpg_ids != old_gpg_ids {
+            line = line + &format!(" -> ({}): {}", new_backend.name(), new_gpg_ids.join(", "));
+        }
+        println!("{}", line);
+        targets.insert(folder.clone(), (new_backend, new_gpg_ids));
+    }
+
+    println!("Keys:");
+    /* the checker of the plan gets the resolutions printed here, so each gpg id is only looked up once */
+    let mut checker = RecipientChecker::new(repo);
+    let mut previewed = HashSet::new();
+    let mut new_lock_files = Vec::new();
+    for &(backend_kind, ref gpg_ids) in targets.values() {
+        for gpg_id in gpg_ids {
+            if previewed.insert((backend_kind, gpg_id.clone())) {
+                let resolution = preview(repo, backend_kind, gpg_id, &mut new_lock_files);
+                checker.add_resolution(backend_kind, gpg_id, resolution);
+            }
+        }
+    }
+
+    println!("Lock files:");
+    if new_lock_files.is_empty() {
+        println!("    none would change");
+    }
+    for lock_file in &new_lock_files {
+        println!("    {} would be created", display_path(repo, lock_file)?);
+    }
+
+    let files = folders.values().flatten().cloned().collect();
+    let plan = reencrypt::plan(repo, files, options, &mut checker, None, |file| {
+        let folder = file.parent().map(|parent| parent.to_path_buf()).unwrap_or(PathBuf::new());
+        Ok(targets[&folder].clone())
+    })?;
+
+    println!("Files to reencrypt:");
+    if plan.files.is_empty() {
+        println!("    none");
+    }
+    for file in &plan.files {
+        println!("    {}", file.display());
+    }
+    if plan.skipped > 0 {
+        println!("{} file(s) already encrypted for their recipients would be left as is", plan.skipped);
+    }
+    plan.preflight.report(options.skip_inaccessible)
+}
+
+/* Prints how the gpg id would resolve, recording the lock files resolving it would create */
+fn preview(repo: &Repository, backend_kind: BackendKind, gpg_id: &str, new_lock_files: &mut Vec<PathBuf>) -> Resolution {
+    if backend_kind == BackendKind::Age {
+        let result = resolver::preview_age_name(repo, gpg_id);
+        return match result {
+            Ok((recipients, lock_file)) => {
+                println!("    {} (age): {}", gpg_id, recipients.join(", "));
+                new_lock_files.extend(lock_file);
+                Ok(recipients)
+            }
+            Err(err) => Err(print_unresolvable(gpg_id, &err)),
+        };
+    }
+
+    let result = backend_kind.create(repo).and_then(|backend| resolver::preview_name(repo, &*backend, gpg_id));
+    match result {
+        Ok(preview) => {
+            println!("    {}: fingerprint {}, key ids {} ({})", gpg_id, preview.fingerprint, preview.key_ids.join(", "), describe_source(&preview.source));
+            new_lock_files.extend(preview.new_lock_file);
+            Ok(preview.key_ids)
+        }
+        Err(err) => Err(print_unresolvable(gpg_id, &err)),
+    }
+}
+
+/* Prints what save-key would change, without touching the store or the keyring */
+pub fn save_key(repo: &Repository, uid: &str, pub_key: Option<&str>) -> Result<()> {
+    let backend = crypto::get_pgp_backend(repo)?;
+    let (fingerprint, key_ids, source) = match pub_key {
+        Some(pub_key) => {
+            let (fingerprint, key_ids) = backend.inspect_key(pub_key)?;
+            (fingerprint, key_ids, "from the given key file")
+        }
+        None => {
+            let preview = resolver::preview_name(repo, &*backend, uid)?;
+            (preview.fingerprint, preview.key_ids, describe_source(&preview.source))
+        }
+    };
+
+    println!("Dry run, nothing is changed");
+    println!("{}: fingerprint {}, key ids {} ({})", uid, fingerprint, key_ids.join(", "), source);
+    if !backend.has_key(&fingerprint)? {
+        println!("The key would be imported into your keyring");
+    }
+
+    let (lock_file, key_file) = resolver::get_save_key_paths(repo, uid, &fingerprint)?;
+    let key_change = if key_file.is_file() { "overwritten" } else { "created" };
+    println!("{} would be {}", display_path(repo, &key_file)?, key_change);
+    if !lock_file.is_file() {
+        println!("{} would be created", display_path(repo, &lock_file)?);
+    } else {
+        let locked_key_id = fs::read_to_string(&lock_file)?.trim().to_string();
+        if locked_key_id == fingerprint {
+            println!("{} would be left as is", display_path(repo, &lock_file)?);
+        } else {
+            println!("{} would change from {} to {}", display_path(repo, &lock_file)?, locked_key_id, fingerprint);
+        }
+    }
+    Ok(())
+}
+
+/* Whether init on `changed_folder` changes the .gpg_id (or .backend) file that applies to `folder`,
+ * that is `folder` is in `changed_folder` and no folder in between has its own */
+fn is_changed(credentials_dir: &Path, changed_folder: &Path, folder: &Path, file_name: &str) -> bool {
+    if !folder.starts_with(changed_folder) {
+        return false;
+    }
+    let mut current = folder;
+    while current != changed_folder {
+        if credentials_dir.join(current).join(file_name).is_file() {
+            return false;
+        }
+        current = current.parent().unwrap();
+    }
+    true
+}
+
+fn print_unresolvable(gpg_id: &str, err: &GitCredError) -> String {
+    println!("    {}: unable to resolve: {}", gpg_id, err);
+    err.to_string()
+}
+
+fn describe_source(source: &KeySource) -> &'static str {
+    match *source {
+        KeySource::Keyring => "in your keyring",
+        KeySource::Store => "would be imported from the store",
+        KeySource::Github => "would be imported from github",
+    }
+}
+
+fn display_folder(folder: &Path) -> String {
+    if folder.as_os_str().is_empty() {
+        return "(root)".to_string();
+    }
+    folder.display().to_string()
+}
+
+/* Displays a path of the store relative to the top of the repo */
+fn display_path(repo: &Repository, path: &Path) -> Result<String> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let relative_path = match path.strip_prefix(&credentials_dir) {
+        Ok(relative_path) => git_utils::get_relative_credentials_dir(repo)?.join(relative_path),
+        Err(_) => path.to_path_buf(),
+    };
+    Ok(relative_path.display().to_string())
+}
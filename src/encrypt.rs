@@ -1,120 +1,198 @@
 use git_utils;
-use gpg;
+use crypto;
+use crypto::BackendKind;
 use git2::Repository;
 use std::path::Path;
-use std::fs::File;
+use std::path::PathBuf;
 use std::fs;
-use std::io::prelude::Read;
+use std::io;
+use error::GitCredError;
+use error::Result;
+use gpg_id;
 use resolver;
 
-pub fn encrypt_file(repo: &Repository, path: &Path, fname: &str) {
-    let mut contents = String::new();
-
-    let file_res = File::open(fname);
-    if file_res.is_err() {
-        panic!("Unable to open file: {}", fname);
-    }
-    let mut file = file_res.unwrap();
-
-    file.read_to_string(&mut contents).unwrap();
-    encrypt_string(repo, path, contents);
+pub fn encrypt_file(repo: &Repository, path: &Path, fname: &str) -> Result<()> {
+    let contents = fs::read(fname)?;
+    encrypt_bytes(repo, path, &contents)
 }
 
-pub fn encrypt_string(repo: &Repository, path: &Path, contents: String) {
-    let gpgs = get_gpgs_for_file(repo, path);
-    let path_to_encrypted_file = git_utils::get_credentials_dir(repo).join(path);
+pub fn encrypt_bytes(repo: &Repository, path: &Path, contents: &[u8]) -> Result<()> {
+    let path_to_encrypted_file = git_utils::get_credentials_dir(repo)?.join(path);
+    let backend_kind = get_backend_kind_for_file(repo, path)?;
+    let gpgs = get_gpgs_for_file(repo, path, backend_kind, true)?;
 
     let mut gpg_pointers: Vec<&str> = Vec::new();
     for gpg in &gpgs {
         gpg_pointers.push(gpg);
     }
     
-    gpg::encrypt_string(&path_to_encrypted_file, contents, &gpg_pointers).unwrap();
+    backend_kind.create(repo)?.encrypt(&path_to_encrypted_file, contents, &gpg_pointers)
 }
 
-pub fn reencrypt_file(repo: &Repository, path: &Path) {
-    println!("reencrypting file: {}", path.display());
-    let gpgs = get_gpgs_for_file(repo, path);
-    let path_to_encrypted_file = git_utils::get_credentials_dir(repo).join(path);
+/* Encrypts into `destination` for whoever a secret at `path` in the store would be encrypted for.
+ * Doesn't create any directories in the store */
+pub fn encrypt_bytes_as(repo: &Repository, path: &Path, destination: &Path, contents: &[u8]) -> Result<()> {
+    let backend_kind = get_backend_kind_for_file(repo, path)?;
+    let gpgs = get_gpgs_for_file(repo, path, backend_kind, false)?;
 
-    let mut gpg_pointers: Vec<&str> = Vec::new();
-    for gpg in &gpgs {
-        gpg_pointers.push(gpg);
-    }
+    let gpg_pointers: Vec<&str> = gpgs.iter().map(|gpg| gpg.as_str()).collect();
+    backend_kind.create(repo)?.encrypt(destination, contents, &gpg_pointers)
+}
+
+/* Returns the backend and resolved recipients a secret at `path` in the store gets encrypted with */
+pub fn get_encryption_target(repo: &Repository, path: &Path) -> Result<(BackendKind, Vec<String>)> {
+    let backend_kind = get_backend_kind_for_file(repo, path)?;
+    let gpgs = get_gpgs_for_file(repo, path, backend_kind, false)?;
+    Ok((backend_kind, gpgs))
+}
+
+/* Decrypts with whichever backend the file was encrypted with and encrypts with the folder's backend */
+pub fn reencrypt_file(repo: &Repository, path: &Path) -> Result<()> {
+    let path_to_encrypted_file = git_utils::get_credentials_dir(repo)?.join(path);
+    let contents = crypto::get_backend_for_ciphertext(repo, &path_to_encrypted_file)?.decrypt(&path_to_encrypted_file)?;
 
-    gpg::reencrypt(&path_to_encrypted_file, &gpg_pointers).unwrap();
+    encrypt_bytes(repo, path, &contents)
 }
 
-pub fn reencrypt_folder(repo: &Repository, path: &Path) {
-    for file in fs::read_dir(path).unwrap() {
-        let file_res = file.unwrap();
-        let file_name = file_res.file_name().into_string().unwrap();
+pub fn list_folder(repo: &Repository, path: &Path) -> Result<Vec<PathBuf>> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut files = Vec::new();
+    for (relative_path, is_dir) in list_entries(repo, path)? {
+        if is_dir {
+            files.append(&mut list_folder(repo, &credentials_dir.join(relative_path))?);
+        } else {
+            files.push(relative_path);
+        }
+    }
+    return Ok(files);
+}
+
+/* Returns the direct children of the folder that aren't dot-entries, as (path relative to the store, is_dir) */
+pub fn list_entries(repo: &Repository, path: &Path) -> Result<Vec<(PathBuf, bool)>> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut entries = Vec::new();
+    for file in fs::read_dir(path)? {
+        let file_res = file?;
+        let file_name = file_res.file_name().to_string_lossy().into_owned();
         if !file_name.starts_with(".") {
-            if file_res.file_type().unwrap().is_dir() {
-                reencrypt_folder(repo, &file_res.path());
-            } else {
-                let total_path = file_res.path();
-                let relative_path = total_path.strip_prefix(git_utils::get_credentials_dir(repo)).unwrap();
-                reencrypt_file(repo, &relative_path);
-            }
+            let total_path = file_res.path();
+            let relative_path = match total_path.strip_prefix(&credentials_dir) {
+                Ok(relative_path) => relative_path.to_path_buf(),
+                Err(_) => return Err(GitCredError::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't in the credential store", path.display())))),
+            };
+            entries.push((relative_path, file_res.file_type()?.is_dir()));
         }
     }
+    entries.sort();
+    return Ok(entries);
 }
 
-/* Creates directories as needed */
-fn get_gpgs_for_file(repo: &Repository, sub_path: &Path) -> Vec<String> {
-    let cred_path = git_utils::get_credentials_dir(repo);
-    let gpgs = read_gpg_id_file(&cred_path);
-    if gpgs.is_none() {
-        panic!("No .gpg_id file in credential store");
+/* Returns the unresolved gpg ids for the file, doesn't create any directories */
+pub fn get_gpg_ids_for_file(repo: &Repository, sub_path: &Path) -> Result<Vec<String>> {
+    get_gpg_ids(repo, sub_path, false)
+}
+
+/* Returns the unresolved gpg ids files directly in the folder are encrypted for */
+pub fn get_gpg_ids_for_folder(repo: &Repository, folder: &Path) -> Result<Vec<String>> {
+    /* the trailing separator makes the last component count as a folder rather than a file */
+    get_gpg_ids(repo, &folder.join(""), false)
+}
+
+/* Returns the backend of the closest .backend file above the file, or the store's backend */
+pub fn get_backend_kind_for_file(repo: &Repository, sub_path: &Path) -> Result<BackendKind> {
+    get_backend_kind_for_folder(repo, sub_path.parent().unwrap_or(Path::new("")))
+}
+
+/* Returns the backend files directly in the folder are encrypted with */
+pub fn get_backend_kind_for_folder(repo: &Repository, folder: &Path) -> Result<BackendKind> {
+    let mut path = git_utils::get_credentials_location(repo)?;
+    let mut backend_name = read_backend_file(&path)?;
+    for component in folder.components() {
+        path = path.join(component);
+        if let Some(name) = read_backend_file(&path)? {
+            backend_name = Some(name);
+        }
     }
-    let sub_paths = sub_path.to_str().unwrap().split(std::path::MAIN_SEPARATOR).collect();
-    let gpgs = get_gpgs_for_file_recursive(&cred_path, sub_paths, gpgs.unwrap());
-    
+
+    match backend_name {
+        Some(name) => BackendKind::from_name(&name),
+        None => crypto::get_backend_kind(repo),
+    }
+}
+
+/* Creates directories as needed if create_dirs is set */
+fn get_gpgs_for_file(repo: &Repository, sub_path: &Path, backend_kind: BackendKind, create_dirs: bool) -> Result<Vec<String>> {
+    let gpgs = get_gpg_ids(repo, sub_path, create_dirs)?;
+    if gpgs.is_empty() {
+        return Err(GitCredError::NoRecipients(sub_path.to_path_buf()));
+    }
+
     let mut resolved_gpgs = Vec::new();
+    if backend_kind == BackendKind::Age {
+        for gpg in &gpgs {
+            resolved_gpgs.append(&mut resolver::resolve_age_name(repo, gpg)?);
+        }
+        return Ok(resolved_gpgs);
+    }
+
+    let backend = backend_kind.create(repo)?;
     for gpg in &gpgs {
-        resolved_gpgs.push(resolver::resolve_name(repo, gpg));
+        resolved_gpgs.push(resolver::resolve_name(repo, &*backend, gpg)?);
+    }
+    return Ok(resolved_gpgs);
+}
+
+fn get_gpg_ids(repo: &Repository, sub_path: &Path, create_dirs: bool) -> Result<Vec<String>> {
+    let cred_path = git_utils::get_credentials_location(repo)?;
+    let gpgs = read_gpg_id_file(&cred_path)?;
+    if gpgs.is_none() {
+        return Err(GitCredError::NoStore(cred_path));
     }
-    return resolved_gpgs;
+    let sub_path_str = sub_path.to_string_lossy();
+    let sub_paths = sub_path_str.split(std::path::MAIN_SEPARATOR).collect();
+    get_gpgs_for_file_recursive(&cred_path, sub_paths, gpgs.unwrap(), create_dirs)
 }
 
-fn get_gpgs_for_file_recursive(path: &Path, sub_path: Vec<&str>, gpgs: Vec<String>) -> Vec<String> {
+fn get_gpgs_for_file_recursive(path: &Path, sub_path: Vec<&str>, gpgs: Vec<String>, create_dirs: bool) -> Result<Vec<String>> {
     let mut sub_path = sub_path;
     let mut gpgs = gpgs;
 
     if sub_path.len() <= 1 {
-        return gpgs;
+        return Ok(gpgs);
     }
     let this_path = path.to_path_buf().join(sub_path.remove(0));
     if !this_path.exists() {
-        fs::create_dir(&this_path).unwrap();
-        return get_gpgs_for_file_recursive(&this_path, sub_path, gpgs);
+        if create_dirs {
+            fs::create_dir(&this_path)?;
+        }
+        return get_gpgs_for_file_recursive(&this_path, sub_path, gpgs, create_dirs);
     }
-    let this_gpg = read_gpg_id_file(&this_path);
+    let this_gpg = read_gpg_id_file(&this_path)?;
     if this_gpg.is_some() {
         gpgs = this_gpg.unwrap();
     }
 
-    get_gpgs_for_file_recursive(&this_path, sub_path, gpgs)
+    get_gpgs_for_file_recursive(&this_path, sub_path, gpgs, create_dirs)
 }
 
-fn read_gpg_id_file(p: &Path) -> Option<Vec<String>> {
+fn read_gpg_id_file(p: &Path) -> Result<Option<Vec<String>>> {
     let mut path = p.to_path_buf();
     path = path.join(".gpg_id");
 
-    let file_res = File::open(path);
-    if file_res.is_err() {
-        return None;
+    if !path.is_file() {
+        return Ok(None);
     }
 
-    let mut file = file_res.unwrap();
-
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+    let contents = fs::read_to_string(path)?;
+    return Ok(Some(gpg_id::parse(&contents)));
+}
 
-    let mut gpg_ids = Vec::new();
-    for gpg in contents.lines() {
-        gpg_ids.push(gpg.to_string());
+fn read_backend_file(p: &Path) -> Result<Option<String>> {
+    let path = p.join(".backend");
+    if !path.is_file() {
+        return Ok(None);
     }
-    return Some(gpg_ids);
-}
\ No newline at end of file
+
+    let contents = fs::read_to_string(path)?;
+    return Ok(Some(contents.trim().to_string()));
+}
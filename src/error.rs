@@ -0,0 +1,132 @@
+use git2;
+use reqwest;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::result;
+
+pub type Result<T> = result::Result<T, GitCredError>;
+
+#[derive(Debug)]
+pub enum GitCredError {
+    /* The credential store (or its root .gpg_id file) is missing */
+    NoStore(PathBuf),
+    /* No gpg ids apply to the given path */
+    NoRecipients(PathBuf),
+    /* No key could be found for the given gpg id */
+    UnresolvableRecipient(String),
+    /* gpg exited unsuccessfully while performing the given operation */
+    GpgFailed { operation: String, stderr: String },
+    /* The native OpenPGP backend failed while performing the given operation */
+    Crypto { operation: String, message: String },
+    /* The creds.backend git config names a backend that isn't available */
+    UnknownBackend(String),
+    /* A template has malformed placeholders or references secrets that don't exist */
+    Template(String),
+    /* The given number of files of the store aren't encrypted for their recipients, the problems are reported separately */
+    CheckFailed(usize),
+    /* The given number of files can't be reencrypted, nothing was changed. The reasons are reported separately */
+    PreflightFailed(usize),
+    Io(io::Error),
+    Git(git2::Error),
+    Network(reqwest::Error),
+}
+
+impl GitCredError {
+    pub fn gpg_failed(operation: &str, stderr: &[u8]) -> GitCredError {
+        GitCredError::GpgFailed {
+            operation: operation.to_string(),
+            stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+        }
+    }
+
+    /* Process exit code for the error, distinct per kind so scripts can tell them apart.
+     * Exit code 2 is reserved for usage errors */
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            GitCredError::NoStore(_) => 3,
+            GitCredError::NoRecipients(_) => 4,
+            GitCredError::UnresolvableRecipient(_) => 5,
+            GitCredError::GpgFailed { .. } => 6,
+            GitCredError::Crypto { .. } => 6,
+            GitCredError::Io(_) => 7,
+            GitCredError::Git(_) => 8,
+            GitCredError::Network(_) => 9,
+            GitCredError::UnknownBackend(_) => 10,
+            GitCredError::Template(_) => 11,
+            GitCredError::CheckFailed(_) => 12,
+            GitCredError::PreflightFailed(_) => 13,
+        }
+    }
+}
+
+impl fmt::Display for GitCredError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GitCredError::NoStore(ref path) => {
+                write!(f, "No credential store (or no .gpg_id file) at: {}", path.display())
+            }
+            GitCredError::NoRecipients(ref path) => {
+                write!(f, "No gpg ids to encrypt for: {}", path.display())
+            }
+            GitCredError::UnresolvableRecipient(ref uid) => {
+                write!(f, "Unable to find key for user: {}", uid)
+            }
+            GitCredError::GpgFailed { ref operation, ref stderr } => {
+                if stderr.is_empty() {
+                    write!(f, "gpg failed to {}", operation)
+                } else {
+                    write!(f, "gpg failed to {}:\n{}", operation, stderr)
+                }
+            }
+            GitCredError::Crypto { ref operation, ref message } => {
+                write!(f, "Failed to {}: {}", operation, message)
+            }
+            GitCredError::UnknownBackend(ref name) => {
+                write!(f, "Unknown or unavailable crypto backend: {}", name)
+            }
+            GitCredError::Template(ref message) => {
+                write!(f, "Invalid template:\n{}", message)
+            }
+            GitCredError::CheckFailed(count) => {
+                write!(f, "{} file(s) in the credential store aren't encrypted properly", count)
+            }
+            GitCredError::PreflightFailed(count) => {
+                write!(f, "{} file(s) can't be reencrypted, nothing was changed", count)
+            }
+            GitCredError::Io(ref err) => write!(f, "I/O error: {}", err),
+            GitCredError::Git(ref err) => write!(f, "git error: {}", err),
+            GitCredError::Network(ref err) => write!(f, "network error: {}", err),
+        }
+    }
+}
+
+impl Error for GitCredError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            GitCredError::Io(ref err) => Some(err),
+            GitCredError::Git(ref err) => Some(err),
+            GitCredError::Network(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GitCredError {
+    fn from(err: io::Error) -> GitCredError {
+        GitCredError::Io(err)
+    }
+}
+
+impl From<git2::Error> for GitCredError {
+    fn from(err: git2::Error) -> GitCredError {
+        GitCredError::Git(err)
+    }
+}
+
+impl From<reqwest::Error> for GitCredError {
+    fn from(err: reqwest::Error) -> GitCredError {
+        GitCredError::Network(err)
+    }
+}
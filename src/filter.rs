@@ -0,0 +1,128 @@
+use git2::ConfigLevel;
+use std::fs;
+use std::path::Path;
+use crypto;
+use encrypt;
+use error::Result;
+use git_utils;
+use merge;
+use private_file::PrivateDir;
+use store::Store;
+
+const ATTRIBUTE: &'static str = "filter=cred";
+const CLEAN_KEY_NAME: &'static str = "filter.cred.clean";
+const SMUDGE_KEY_NAME: &'static str = "filter.cred.smudge";
+const REQUIRED_KEY_NAME: &'static str = "filter.cred.required";
+
+/// Git clean filter: encrypts the plaintext of the file at `path` (relative to the top of the
+/// repo) for whoever a secret at the same path in the store would be encrypted for.
+///
+/// Encryption isn't deterministic, so if the staged version of the file already holds the same
+/// plaintext, the staged ciphertext is returned as is rather than making the file look modified.
+pub fn clean(store: &Store, path: &Path, input: &[u8]) -> Result<Vec<u8>> {
+    let repo = store.repo();
+    let staged = match repo.index()?.get_path(path, 0) {
+        Some(entry) => Some(repo.find_blob(entry.id)?.content().to_vec()),
+        None => None,
+    };
+
+    let dir = PrivateDir::new()?;
+    if let Some(staged) = staged {
+        /* a file left encrypted by smudge for someone without access is cleaned to itself */
+        if staged == input {
+            return Ok(staged);
+        }
+        if let Ok(plaintext) = decrypt_bytes(store, &dir, &staged) {
+            if plaintext == input {
+                return Ok(staged);
+            }
+        }
+    }
+
+    let destination = dir.path().join("encrypted");
+    encrypt::encrypt_bytes_as(repo, path, &destination, input)?;
+    Ok(fs::read(destination)?)
+}
+
+/// Git smudge filter: decrypts the file at `path`. Files that can't be decrypted, e.g. because
+/// the user isn't one of its recipients, are left encrypted so the checkout still succeeds.
+pub fn smudge(store: &Store, path: &Path, input: &[u8]) -> Result<Vec<u8>> {
+    let dir = PrivateDir::new()?;
+    match decrypt_bytes(store, &dir, input) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(err) => {
+            eprintln!("git-cred: leaving {} encrypted: {}", path.display(), err);
+            Ok(input.to_vec())
+        }
+    }
+}
+
+/// Registers the filter in the repo's git config, and marks the files matching `patterns`
+/// as filtered in the `.gitattributes` file at the top of the repo, along with the merge driver.
+pub fn install(store: &Store, patterns: &[String]) -> Result<()> {
+    let repo = store.repo();
+    let mut config = repo.config()?.open_level(ConfigLevel::Local)?;
+    config.set_str(CLEAN_KEY_NAME, "git cred filter clean %f")?;
+    config.set_str(SMUDGE_KEY_NAME, "git cred filter smudge %f")?;
+    /* never fall back to committing plaintext if git-cred can't be run */
+    config.set_bool(REQUIRED_KEY_NAME, true)?;
+
+    git_utils::add_attributes(repo, patterns, ATTRIBUTE)?;
+    /* filtered files are stored encrypted, so they need the same merge driver as the store */
+    git_utils::add_attributes(repo, patterns, "merge=cred")?;
+    merge::install(store)
+}
+
+fn decrypt_bytes(store: &Store, dir: &PrivateDir, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let path = dir.write_file("ciphertext", ciphertext)?;
+    crypto::get_backend_for_ciphertext(store.repo(), &path)?.decrypt(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use test_utils::TestRepo;
+    use super::*;
+
+    #[test]
+    fn smudge_decrypts_what_clean_encrypted() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        let path = Path::new("config/secrets.yml");
+
+        let ciphertext = clean(&store, path, b"password: hunter2\n").unwrap();
+        assert!(!ciphertext.windows(7).any(|window| window == b"hunter2"));
+        assert_eq!(smudge(&store, path, &ciphertext).unwrap(), b"password: hunter2\n");
+    }
+
+    #[test]
+    fn clean_keeps_the_staged_ciphertext_if_the_plaintext_is_unchanged() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        let path = Path::new("secrets.yml");
+
+        let staged = clean(&store, path, b"password: hunter2\n").unwrap();
+        fs::write(test_repo.top_level().join(path), &staged).unwrap();
+        let mut index = test_repo.repo.index().unwrap();
+        index.add_path(path).unwrap();
+        index.write().unwrap();
+
+        assert_eq!(clean(&store, path, b"password: hunter2\n").unwrap(), staged);
+        assert_eq!(clean(&store, path, &staged).unwrap(), staged);
+        let changed = clean(&store, path, b"password: hunter3\n").unwrap();
+        assert_ne!(changed, staged);
+        assert_eq!(smudge(&store, path, &changed).unwrap(), b"password: hunter3\n");
+    }
+
+    #[test]
+    fn smudge_leaves_what_it_cant_decrypt() {
+        let test_repo = TestRepo::new();
+        let other_repo = TestRepo::new();
+        let path = Path::new("secrets.yml");
+
+        let ciphertext = clean(&other_repo.store(), path, b"password: hunter2\n").unwrap();
+        assert_eq!(smudge(&test_repo.store(), path, &ciphertext).unwrap(), ciphertext);
+        assert_eq!(smudge(&test_repo.store(), path, b"not encrypted").unwrap(), b"not encrypted");
+    }
+}
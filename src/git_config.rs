@@ -1,23 +1,78 @@
 use git2::Repository;
 use std::path::PathBuf;
+use error::Result;
 
 const LOCATION_KEY_NAME: &'static str = "creds.location";
+const BACKEND_KEY_NAME: &'static str = "creds.backend";
+const IDENTITY_KEY_NAME: &'static str = "creds.identity";
+const HELPER_FOLDER_KEY_NAME: &'static str = "creds.helperfolder";
+#[cfg(feature = "native-pgp")]
+const KEYRING_KEY_NAME: &'static str = "creds.keyring";
+#[cfg(feature = "native-pgp")]
+const SECRET_KEY_KEY_NAME: &'static str = "creds.secretkey";
 
-pub fn get_credentials_location(repo: &Repository) -> Option<PathBuf> {
-    let config = repo.config().unwrap();
+pub fn get_credentials_location(repo: &Repository) -> Result<Option<PathBuf>> {
+    let config = repo.config()?;
 
     let possible_location = config.get_path(LOCATION_KEY_NAME);
     if possible_location.is_err() {
-        return None;
+        return Ok(None);
     }
-    return Some(possible_location.unwrap());
+    return Ok(Some(possible_location.unwrap()));
 }
 
-pub fn get_email(repo: &Repository) -> Option<String> {
-    let config = repo.config().unwrap();
+pub fn get_email(repo: &Repository) -> Result<Option<String>> {
+    let config = repo.config()?;
     let possible_email = config.get_string("user.email");
     if possible_email.is_err() {
-        return None;
+        return Ok(None);
     }
-    return Some(possible_email.unwrap());
-}
\ No newline at end of file
+    return Ok(Some(possible_email.unwrap()));
+}
+
+pub fn get_backend(repo: &Repository) -> Result<Option<String>> {
+    let config = repo.config()?;
+    let possible_backend = config.get_string(BACKEND_KEY_NAME);
+    if possible_backend.is_err() {
+        return Ok(None);
+    }
+    return Ok(Some(possible_backend.unwrap()));
+}
+
+pub fn get_identity_location(repo: &Repository) -> Result<Option<PathBuf>> {
+    let config = repo.config()?;
+    let possible_location = config.get_path(IDENTITY_KEY_NAME);
+    if possible_location.is_err() {
+        return Ok(None);
+    }
+    return Ok(Some(possible_location.unwrap()));
+}
+
+pub fn get_helper_folder(repo: &Repository) -> Result<Option<PathBuf>> {
+    let config = repo.config()?;
+    let possible_folder = config.get_path(HELPER_FOLDER_KEY_NAME);
+    if possible_folder.is_err() {
+        return Ok(None);
+    }
+    return Ok(Some(possible_folder.unwrap()));
+}
+
+#[cfg(feature = "native-pgp")]
+pub fn get_keyring_location(repo: &Repository) -> Result<Option<PathBuf>> {
+    let config = repo.config()?;
+    let possible_location = config.get_path(KEYRING_KEY_NAME);
+    if possible_location.is_err() {
+        return Ok(None);
+    }
+    return Ok(Some(possible_location.unwrap()));
+}
+
+#[cfg(feature = "native-pgp")]
+pub fn get_secret_key_location(repo: &Repository) -> Result<Option<PathBuf>> {
+    let config = repo.config()?;
+    let possible_location = config.get_path(SECRET_KEY_KEY_NAME);
+    if possible_location.is_err() {
+        return Ok(None);
+    }
+    return Ok(Some(possible_location.unwrap()));
+}
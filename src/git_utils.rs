@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use git2::Repository;
 use git_config;
 use std::fs;
+use error::Result;
 
 pub fn get_top_level_directory(repo: &Repository) -> &Path {
     let path = repo.path();
@@ -19,29 +20,33 @@ pub fn get_credentials_dir_path(repo: &Repository) -> PathBuf {
     return path;
 }
 
+/* Returns the absolute path to the credential store, without creating it */
+pub fn get_credentials_location(repo: &Repository) -> Result<PathBuf> {
+    let possible_credentials_location = git_config::get_credentials_location(repo)?;
+    if possible_credentials_location.is_none() {
+        return Ok(get_credentials_dir_path(repo));
+    }
+    let mut top_level = get_top_level_directory(repo).to_path_buf();
+    top_level.push(possible_credentials_location.unwrap());
+    Ok(top_level)
+}
+
 /* Creates credential store if it doesn't exist. Returns the absolute path to the credential store */
-pub fn get_credentials_dir(repo: &Repository) -> PathBuf {
-    let possible_credentials_location = git_config::get_credentials_location(repo);
-    let location = if possible_credentials_location.is_none() {
-        get_credentials_dir_path(repo)
-    } else {
-        let mut top_level = get_top_level_directory(repo).to_path_buf();
-        top_level.push(possible_credentials_location.unwrap());
-        top_level
-    };
+pub fn get_credentials_dir(repo: &Repository) -> Result<PathBuf> {
+    let location = get_credentials_location(repo)?;
     if !location.exists() {
-        println!("Creating new credential store at: {}", location.display());
-        fs::create_dir(location.clone()).unwrap();
-        create_default_gpg_id(repo, &location);
+        eprintln!("Creating new credential store at: {}", location.display());
+        fs::create_dir(location.clone())?;
+        create_default_gpg_id(repo, &location)?;
     }
-    return location;
+    return Ok(location);
 }
 
-fn create_default_gpg_id(repo: &Repository, path: &Path) {
-    let default_email = git_config::get_email(repo);
+fn create_default_gpg_id(repo: &Repository, path: &Path) -> Result<()> {
+    let default_email = git_config::get_email(repo)?;
     let string_to_write = if default_email.is_some() {
         let mut default_email_res = default_email.unwrap();
-        println!("Using default gpg id: {}", default_email_res);
+        eprintln!("Using default gpg id: {}", default_email_res);
         default_email_res += "\n";
         default_email_res
     } else {
@@ -49,22 +54,77 @@ fn create_default_gpg_id(repo: &Repository, path: &Path) {
     };
 
     let gpg_path = path.to_path_buf().join(".gpg_id");
-    fs::write(gpg_path, string_to_write).unwrap();
+    fs::write(gpg_path, string_to_write)?;
+    Ok(())
 }
 
-pub fn create_sub_dir(repo: &Repository, path: &Path, recipients: Vec<String>) {
-    let actual_path = get_credentials_dir_path(repo).join(path);
-    fs::create_dir_all(actual_path.clone()).unwrap();
+pub fn create_sub_dir(repo: &Repository, path: &Path, recipients: Vec<String>) -> Result<()> {
+    let actual_path = get_credentials_dir(repo)?.join(path);
+    fs::create_dir_all(actual_path.clone())?;
 
     let mut string_to_write = String::new();
     if recipients.is_empty() {
-        string_to_write = git_config::get_email(repo).unwrap_or(String::new());
-        string_to_write += "\n";
+        if let Some(email) = git_config::get_email(repo)? {
+            string_to_write = email + "\n";
+        }
     } else {
         for recipient in recipients {
             string_to_write = string_to_write + &recipient + "\n";
         }
     }
     let gpg_path = actual_path.join(".gpg_id");
-    fs::write(gpg_path, string_to_write).unwrap();
-}
\ No newline at end of file
+    fs::write(gpg_path, string_to_write)?;
+    Ok(())
+}
+
+pub fn write_backend_file(repo: &Repository, path: &Path, backend: &str) -> Result<()> {
+    let actual_path = get_credentials_dir(repo)?.join(path);
+    fs::create_dir_all(actual_path.clone())?;
+    fs::write(actual_path.join(".backend"), backend.to_string() + "\n")?;
+    Ok(())
+}
+
+/* Returns the path of the credential store relative to the top of the repo */
+pub fn get_relative_credentials_dir(repo: &Repository) -> Result<PathBuf> {
+    let credentials_dir = get_credentials_location(repo)?;
+    match credentials_dir.strip_prefix(get_top_level_directory(repo)) {
+        Ok(relative_path) => Ok(relative_path.to_path_buf()),
+        Err(_) => Ok(credentials_dir.clone()),
+    }
+}
+
+/* Sets `<attribute>=cred` on the secrets of the store in .gitattributes, but not on its dot-files */
+pub fn add_store_attributes(repo: &Repository, attribute: &str) -> Result<()> {
+    let store_pattern = get_relative_credentials_dir(repo)?.to_string_lossy().into_owned();
+    add_attributes(repo, &[format!("{}/**", store_pattern)], &format!("{}=cred", attribute))?;
+    add_attributes(repo, &[format!("{}/**/.*", store_pattern), format!("{}/.keys/**", store_pattern)], &format!("!{}", attribute))
+}
+
+/* Appends `<pattern> <attribute>` lines to the .gitattributes file at the top of the repo,
+ * skipping the ones already there */
+pub fn add_attributes(repo: &Repository, patterns: &[String], attribute: &str) -> Result<()> {
+    let attributes_path = get_top_level_directory(repo).join(".gitattributes");
+    let mut contents = if attributes_path.is_file() {
+        fs::read_to_string(&attributes_path)?
+    } else {
+        String::new()
+    };
+
+    let original_length = contents.len();
+    for pattern in patterns {
+        let line = format!("{} {}", pattern, attribute);
+        if contents.lines().any(|existing| existing.trim() == line) {
+            continue;
+        }
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+
+    if contents.len() != original_length {
+        fs::write(attributes_path, contents)?;
+    }
+    Ok(())
+}
@@ -1,28 +1,23 @@
 
 use serde_json::value::Value;
 use reqwest::get;
+use error::Result;
 
-pub fn get_key(user: &str, key_id: Option<String>) -> Option<String> {
+pub fn get_key(user: &str, key_id: Option<String>) -> Result<Option<String>> {
     let url = format!("https://api.github.com/users/{}/gpg_keys", user);
-    let response = get(&url);
-    if response.is_err() {
-        return None;
+    let mut response = get(&url)?;
+    if !response.status().is_success() {
+        return Ok(None);
     }
 
-    let mut unwrapped_response = response.unwrap();
-    let key_array: reqwest::Result<Value> = unwrapped_response.json();
-    if key_array.is_err() {
-        return None;
-    }
-
-    let key_array_res = key_array.unwrap();
+    let key_array: Value = response.json()?;
 
-    let keys = match key_array_res {
+    let keys = match key_array {
         Value::Array(keys) => {
             keys
         },
         _ => {
-            return None;
+            return Ok(None);
         }
     };
 
@@ -31,10 +26,7 @@ pub fn get_key(user: &str, key_id: Option<String>) -> Option<String> {
             Value::Object(omap) => {
                 if key_id.is_none() {
                     if omap.contains_key("raw_key") {
-                        let mut key_string = omap.get("raw_key").as_ref().unwrap().to_string();
-                        key_string = key_string.replace("\"", "");
-                        key_string = key_string.replace("\\r\\n", "\n");
-                        return Some(key_string);
+                        return Ok(Some(raw_key_string(omap.get("raw_key").unwrap())));
                     }
                 } else {
                     if omap.contains_key("key_id") {
@@ -42,10 +34,7 @@ pub fn get_key(user: &str, key_id: Option<String>) -> Option<String> {
                         if omap_key_id.is_string() {
                             if key_id.as_ref().unwrap().ends_with(omap_key_id.as_str().unwrap()) {
                                 if omap.contains_key("raw_key") {
-                                    let mut key_string = omap.get("raw_key").as_ref().unwrap().to_string();
-                                    key_string = key_string.replace("\"", "");
-                                    key_string = key_string.replace("\\r\\n", "\n");
-                                    return Some(key_string);
+                                    return Ok(Some(raw_key_string(omap.get("raw_key").unwrap())));
                                 }
                             }
                         }
@@ -53,9 +42,28 @@ pub fn get_key(user: &str, key_id: Option<String>) -> Option<String> {
                 }
             },
             _ => {
-                return None;
+                return Ok(None);
             }
         }
     }
-    return None;
-}
\ No newline at end of file
+    return Ok(None);
+}
+
+fn raw_key_string(raw_key: &Value) -> String {
+    let mut key_string = raw_key.to_string();
+    key_string = key_string.replace("\"", "");
+    key_string = key_string.replace("\\r\\n", "\n");
+    key_string
+}
+
+/* Returns the ssh public keys of a github user, one key per entry */
+pub fn get_ssh_keys(user: &str) -> Result<Vec<String>> {
+    let url = format!("https://github.com/{}.keys", user);
+    let mut response = get(&url)?;
+    if !response.status().is_success() {
+        return Ok(Vec::new());
+    }
+
+    let keys = response.text()?;
+    return Ok(keys.lines().map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect());
+}
@@ -4,23 +4,62 @@ use std::process::Stdio;
 use std::io;
 use std::io::Write;
 use command;
-use util;
+use crypto::CryptoBackend;
+use error::GitCredError;
+use error::Result;
 
-pub fn decrypt<P: AsRef<Path>>(path: P) -> io::Result<String> {
-    let output = command::run_command("gpg", vec!["--decrypt", path.as_ref().to_str().unwrap()]);
-    if output.is_err() {
-        return Err(output.err().unwrap());
+/* Backend shelling out to the gpg binary, using the user's gpg keyring */
+pub struct GpgBackend;
+
+impl CryptoBackend for GpgBackend {
+    fn decrypt(&self, path: &Path) -> Result<Vec<u8>> {
+        decrypt(path)
+    }
+
+    fn encrypt(&self, path: &Path, contents: &[u8], recipients: &Vec<&str>) -> Result<()> {
+        encrypt(path, contents, recipients)
+    }
+
+    fn has_key(&self, id: &str) -> Result<bool> {
+        has_key(id)
+    }
+
+    fn get_key_id(&self, id: &str) -> Result<String> {
+        get_key_id(id)
+    }
+
+    fn get_key_ids(&self, id: &str) -> Result<Vec<String>> {
+        get_key_ids(id)
+    }
+
+    fn get_secret_key_ids(&self) -> Result<Option<Vec<String>>> {
+        get_secret_key_ids().map(Some)
+    }
+
+    fn import_key(&self, key_contents: &str) -> Result<String> {
+        import_key(key_contents)
+    }
+
+    fn inspect_key(&self, key_contents: &str) -> Result<(String, Vec<String>)> {
+        inspect_key(key_contents)
     }
-    let output_unwrapped = output.unwrap();
-    if !output_unwrapped.status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "Decryption failed"));
+
+    fn export_key(&self, id: &str) -> Result<String> {
+        export_key(id)
     }
+}
 
-    return Ok(String::from_utf8(output_unwrapped.stdout).unwrap());
+fn decrypt<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let output = command::run_command("gpg", vec!["--decrypt", path_str(path.as_ref())?])?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed(&format!("decrypt {}", path.as_ref().display()), &output.stderr));
+    }
+
+    return Ok(output.stdout);
 }
 
-pub fn encrypt_string<P: AsRef<Path>>(path: P, contents: String, recipients: &Vec<&str>) -> io::Result<()> {
-    let path_str = path.as_ref().to_str().unwrap();
+fn encrypt<P: AsRef<Path>>(path: P, contents: &[u8], recipients: &Vec<&str>) -> Result<()> {
+    let path_str = path_str(path.as_ref())?;
     let mut args = vec!["--encrypt", "--trust-model", "always", "--batch", "--yes", "--output", path_str];
 
     for recipient in recipients {
@@ -28,82 +67,207 @@ pub fn encrypt_string<P: AsRef<Path>>(path: P, contents: String, recipients: &Ve
         args.push(recipient);
     }
     
-    let mut output = Command::new("gpg")
+    let mut child = Command::new("gpg")
         .args(args)
         .stdin(Stdio::piped())
-        .spawn()
-        .expect("Unable to call gpg");
+        .stderr(Stdio::piped())
+        .spawn()?;
     
-    output.stdin.as_mut().unwrap().write_all(&contents.into_bytes()).unwrap();
-    let exit_code = output.wait().unwrap();
-    if !exit_code.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "Encryption failed"));
+    child.stdin.as_mut().unwrap().write_all(contents)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed(&format!("encrypt {}", path_str), &output.stderr));
     } else {
         return Ok(());
     }
 }
 
-pub fn reencrypt<P: AsRef<Path>>(path: P, recipients: &Vec<&str>) -> io::Result<()> {
-    let contents = decrypt(&path);
-    if contents.is_err() {
-        return Err(contents.err().unwrap());
+fn has_key(id: &str) -> Result<bool> {
+    let output = command::run_command("gpg", vec!["--list-keys", id])?;
+    return Ok(output.status.success());
+}
+
+/* Returns the fingerprint of the first key matching the id */
+fn get_key_id(id: &str) -> Result<String> {
+    let output = command::run_command("gpg", vec!["--with-colons", "--list-keys", id])?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed(&format!("list key {}", id), &output.stderr));
     }
 
-    encrypt_string(path, contents.unwrap(), recipients)
+    match read_fingerprint(&utf8(output.stdout)?) {
+        Some(fingerprint) => Ok(fingerprint),
+        None => Err(GitCredError::UnresolvableRecipient(id.to_string())),
+    }
 }
 
-pub fn has_key(id: &str) -> bool {
-    let output = command::run_command("gpg", vec!["--list-keys", id]).unwrap();
-    return output.status.success();
+fn get_key_ids(id: &str) -> Result<Vec<String>> {
+    let output = command::run_command("gpg", vec!["--with-colons", "--list-keys", id])?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed(&format!("list key {}", id), &output.stderr));
+    }
+
+    /* the key id is the fifth field of the pub and sub records */
+    let key_ids = utf8(output.stdout)?
+        .lines()
+        .filter(|line| line.starts_with("pub:") || line.starts_with("sub:"))
+        .filter_map(|line| line.split(':').nth(4))
+        .map(|key_id| key_id.to_uppercase())
+        .collect();
+    Ok(key_ids)
 }
 
-pub fn get_key_id(id: &str) -> String {
-    let output = command::run_command("gpg", vec!["--keyid-format", "LONG", "-k", id]).unwrap();
-    let output_string: String = String::from_utf8(output.stdout).unwrap();
+fn get_secret_key_ids() -> Result<Vec<String>> {
+    let output = command::run_command("gpg", vec!["--with-colons", "--list-secret-keys"])?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed("list secret keys", &output.stderr));
+    }
 
-    output_string.lines().nth(1).unwrap().trim().to_string()
+    /* the fifteenth field starts with '#' for secret keys that aren't actually available, e.g. offline keys */
+    let key_ids = utf8(output.stdout)?
+        .lines()
+        .filter(|line| line.starts_with("sec:") || line.starts_with("ssb:"))
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .filter(|fields| fields.len() > 4 && !fields.get(14).unwrap_or(&"").starts_with('#'))
+        .map(|fields| fields[4].to_uppercase())
+        .collect();
+    Ok(key_ids)
 }
 
-pub fn import_key(key_contents: &str) -> String {
+fn import_key(key_contents: &str) -> Result<String> {
     return import_key_bytes(&key_contents.to_string().into_bytes());
 }
 
-// returns the key-id of the imported key
-pub fn import_key_bytes(key_contents: &Vec<u8>) -> String {
-    let mut output = Command::new("gpg")
-        .args(vec!["--import"])
+// returns the fingerprint of the imported key
+fn import_key_bytes(key_contents: &Vec<u8>) -> Result<String> {
+    let mut child = Command::new("gpg")
+        .args(vec!["--batch", "--status-fd", "1", "--import"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()
-        .expect("Unable to call gpg");
-    
-    output.stdin.as_mut().unwrap().write_all(key_contents).unwrap();
+        .spawn()?;
     
+    child.stdin.as_mut().unwrap().write_all(key_contents)?;
 
-    let exit_cond = output.wait_with_output().unwrap();
-    if !exit_cond.status.success() {
-        let output_str = String::from_utf8(exit_cond.stderr).unwrap();
-        println!("error is: {}", output_str);
-        util::error_out("Unable to import key!");
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed("import key", &output.stderr));
     }
 
-    let output_str = String::from_utf8(exit_cond.stderr).unwrap();
+    match read_imported_fingerprint(&utf8(output.stdout)?) {
+        Some(fingerprint) => Ok(fingerprint),
+        None => Err(GitCredError::GpgFailed {
+            operation: "read key id of imported key".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }),
+    }
+}
+
+fn inspect_key(key_contents: &str) -> Result<(String, Vec<String>)> {
+    let mut child = Command::new("gpg")
+        .args(vec!["--with-colons", "--import-options", "show-only", "--import"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    child.stdin.as_mut().unwrap().write_all(key_contents.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed("read key", &output.stderr));
+    }
 
-    let output_lines: Vec<&str> = output_str.lines().collect();
-    let first_line = output_lines[0];
-    let words: Vec<&str> = first_line.split(" ").collect();
-    let mut short_id = words[2].to_string();
-    let short_id_len = short_id.len();
-    short_id.remove(short_id_len - 1);
-    return get_key_id(&short_id);
+    let output_str = utf8(output.stdout)?;
+    let fingerprint = read_fingerprint(&output_str);
+    let key_ids = output_str.lines()
+        .filter(|line| line.starts_with("pub:") || line.starts_with("sub:"))
+        .filter_map(|line| line.split(':').nth(4))
+        .map(|key_id| key_id.to_uppercase())
+        .collect();
+    match fingerprint {
+        Some(fingerprint) => Ok((fingerprint, key_ids)),
+        None => Err(GitCredError::GpgFailed {
+            operation: "read key".to_string(),
+            stderr: "no key found".to_string(),
+        }),
+    }
 }
 
-pub fn export_key(uid: &str) -> Option<String> {
-    let output = command::run_command("gpg", vec!["--export",  "--armor", uid]);
-    if output.is_err() {
-        return None;
+fn export_key(uid: &str) -> Result<String> {
+    let output = command::run_command("gpg", vec!["--export",  "--armor", uid])?;
+    if !output.status.success() {
+        return Err(GitCredError::gpg_failed(&format!("export key {}", uid), &output.stderr));
+    }
+    if output.stdout.is_empty() {
+        return Err(GitCredError::UnresolvableRecipient(uid.to_string()));
     }
 
-    return Some(String::from_utf8(output.unwrap().stdout).unwrap());
-}
\ No newline at end of file
+    return utf8(output.stdout);
+}
+
+/* The fingerprint of the first primary key in --with-colons output is the tenth field of the
+ * fpr record following its pub record */
+fn read_fingerprint(colons_output: &str) -> Option<String> {
+    colons_output.lines()
+        .skip_while(|line| !line.starts_with("pub:"))
+        .find(|line| line.starts_with("fpr:"))
+        .and_then(|line| line.split(':').nth(9))
+        .filter(|fingerprint| !fingerprint.is_empty())
+        .map(|fingerprint| fingerprint.to_uppercase())
+}
+
+/* gpg reports each imported key with an `IMPORT_OK <reasons> <fingerprint>` status line, unchanged keys included */
+fn read_imported_fingerprint(status_output: &str) -> Option<String> {
+    status_output.lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] IMPORT_OK "))
+        .filter_map(|fields| fields.split(' ').nth(1))
+        .find(|fingerprint| !fingerprint.is_empty())
+        .map(|fingerprint| fingerprint.to_uppercase())
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    match path.to_str() {
+        Some(path_str) => Ok(path_str),
+        None => Err(GitCredError::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("Path isn't valid UTF-8: {}", path.display())))),
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String> {
+    match String::from_utf8(bytes) {
+        Ok(string) => Ok(string),
+        Err(err) => Err(GitCredError::Io(io::Error::new(io::ErrorKind::InvalidData, err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST_KEYS: &'static str = "tru::1:1792320293:0:3:1:5\n\
+        pub:u:3072:1:88C9CCF96C2EB0EF:1792318754:::u:::scESC::::::23::0:\n\
+        fpr:::::::::1B148FBB0343047A45DB762388C9CCF96C2EB0EF:\n\
+        uid:u::::1792318754::71949CF8FB0D5D1F241F7542DF7C353DD97CC943::t@example.com::::::::::0:\n\
+        sub:u:3072:1:5F79F656751467C4:1792318754::::::e::::::23:\n\
+        fpr:::::::::A3AB3A5E0A9C3F1D3C1B6D4E5F79F656751467C4:\n\
+        pub:u:3072:1:2D1C1A7C0E1D52C0:1792318800:::u:::scESC::::::23::0:\n\
+        fpr:::::::::5A7B56910F3E6D6B1A1F11F32D1C1A7C0E1D52C0:\n";
+
+    #[test]
+    fn reads_the_fingerprint_of_the_first_key() {
+        assert_eq!(read_fingerprint(LIST_KEYS), Some("1B148FBB0343047A45DB762388C9CCF96C2EB0EF".to_string()));
+        assert_eq!(read_fingerprint("fpr:::::::::A3AB3A5E0A9C3F1D3C1B6D4E5F79F656751467C4:\n"), None);
+        assert_eq!(read_fingerprint("pub:u:3072:1:88C9CCF96C2EB0EF:1792318754:::u:::scESC::::::23::0:\nfpr:::\n"), None);
+        assert_eq!(read_fingerprint(""), None);
+    }
+
+    #[test]
+    fn reads_the_fingerprint_of_the_imported_key() {
+        let status = "[GNUPG:] KEY_CONSIDERED 1B148FBB0343047A45DB762388C9CCF96C2EB0EF 0\n\
+            [GNUPG:] IMPORTED 88C9CCF96C2EB0EF t@example.com\n\
+            [GNUPG:] IMPORT_OK 1 1b148fbb0343047a45db762388c9ccf96c2eb0ef\n\
+            [GNUPG:] IMPORT_OK 0 5A7B56910F3E6D6B1A1F11F32D1C1A7C0E1D52C0\n\
+            [GNUPG:] IMPORT_RES 2 0 1 0 1 0 0 0 0 0 0 0 0 0 0\n";
+        assert_eq!(read_imported_fingerprint(status), Some("1B148FBB0343047A45DB762388C9CCF96C2EB0EF".to_string()));
+        assert_eq!(read_imported_fingerprint("[GNUPG:] IMPORT_PROBLEM 1\n[GNUPG:] IMPORT_RES 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n"), None);
+        assert_eq!(read_imported_fingerprint("gpg: key 88C9CCF96C2EB0EF: public key \"t@example.com\" imported\n"), None);
+    }
+}
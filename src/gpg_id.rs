@@ -0,0 +1,169 @@
+/* Reads and edits .gpg_id files: one gpg id per line, blank lines and lines starting with '#'
+ * are ignored. Edits keep the other lines as they are */
+
+use git2::Repository;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use crypto::BackendKind;
+use dryrun;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use reencrypt;
+use reencrypt::ReencryptOptions;
+use resolver;
+
+const GPG_ID_FILE_NAME: &'static str = ".gpg_id";
+const COMMENT_PREFIX: &'static str = "#";
+
+pub fn parse(contents: &str) -> Vec<String> {
+    contents.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with(COMMENT_PREFIX))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/* Appends the gpg ids that aren't in the contents yet. Returns the new contents and the gpg ids added */
+pub fn add(contents: &str, gpg_ids: &[String]) -> (String, Vec<String>) {
+    let mut existing = parse(contents);
+    let mut new_contents = contents.to_string();
+    if !new_contents.is_empty() && !new_contents.ends_with('\n') {
+        new_contents.push('\n');
+    }
+
+    let mut added = Vec::new();
+    for gpg_id in gpg_ids {
+        if existing.contains(gpg_id) {
+            continue;
+        }
+        new_contents = new_contents + gpg_id + "\n";
+        existing.push(gpg_id.clone());
+        added.push(gpg_id.clone());
+    }
+    (new_contents, added)
+}
+
+/* Removes the lines of the given gpg ids */
+pub fn remove(contents: &str, gpg_ids: &[String]) -> String {
+    let mut new_contents = String::new();
+    for line in contents.lines() {
+        if !gpg_ids.iter().any(|gpg_id| gpg_id == line.trim()) {
+            new_contents = new_contents + line + "\n";
+        }
+    }
+    new_contents
+}
+
+/* Adds gpg ids to the .gpg_id file of the folder and reencrypts the folder. The new gpg ids are
+ * resolved first, so that nothing changes if one of them can't be. Returns the gpg ids added */
+pub fn add_recipients(repo: &Repository, folder: &Path, gpg_ids: &[String], options: &ReencryptOptions) -> Result<Vec<String>> {
+    let (new_contents, added) = add(&read_contents(repo, folder)?, gpg_ids);
+    if added.is_empty() {
+        return Ok(added);
+    }
+
+    if !options.dry_run {
+        let backend_kind = encrypt::get_backend_kind_for_folder(repo, folder)?;
+        let backend = backend_kind.create(repo)?;
+        for gpg_id in &added {
+            match backend_kind {
+                BackendKind::Age => resolver::resolve_age_name(repo, gpg_id).map(|_| ())?,
+                _ => resolver::resolve_name(repo, &*backend, gpg_id).map(|_| ())?,
+            }
+        }
+    }
+    write_contents(repo, folder, &new_contents, options)?;
+    Ok(added)
+}
+
+/* Removes gpg ids from the .gpg_id file of the folder and reencrypts the folder. Fails if one of them
+ * isn't a gpg id of the folder, or if the folder would be left without gpg ids */
+pub fn remove_recipients(repo: &Repository, folder: &Path, gpg_ids: &[String], options: &ReencryptOptions) -> Result<()> {
+    let contents = read_contents(repo, folder)?;
+    let current_gpg_ids = parse(&contents);
+    if let Some(gpg_id) = gpg_ids.iter().find(|gpg_id| !current_gpg_ids.contains(gpg_id)) {
+        return Err(GitCredError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't a gpg id of {}", gpg_id, display_folder(folder)))));
+    }
+
+    let new_contents = remove(&contents, gpg_ids);
+    if parse(&new_contents).is_empty() {
+        return Err(GitCredError::NoRecipients(folder.to_path_buf()));
+    }
+    write_contents(repo, folder, &new_contents, options)
+}
+
+/* Returns the folder whose .gpg_id file applies to the folder, the folder itself or one of its parents */
+pub fn find_gpg_id_folder(repo: &Repository, folder: &Path) -> Result<PathBuf> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut current = folder;
+    while !credentials_dir.join(current).join(GPG_ID_FILE_NAME).is_file() {
+        current = match current.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    Ok(current.to_path_buf())
+}
+
+/* The contents of the .gpg_id file of the folder or, if it doesn't have one, the gpg ids it inherits */
+fn read_contents(repo: &Repository, folder: &Path) -> Result<String> {
+    let path = git_utils::get_credentials_location(repo)?.join(folder).join(GPG_ID_FILE_NAME);
+    if path.is_file() {
+        return Ok(fs::read_to_string(path)?);
+    }
+    let gpg_ids = encrypt::get_gpg_ids_for_folder(repo, folder)?;
+    Ok(gpg_ids.iter().map(|gpg_id| gpg_id.clone() + "\n").collect())
+}
+
+fn write_contents(repo: &Repository, folder: &Path, contents: &str, options: &ReencryptOptions) -> Result<()> {
+    if options.dry_run {
+        return dryrun::init(repo, folder, parse(contents), None, options);
+    }
+    reencrypt::write_gpg_id_file(repo, folder, contents, options)
+}
+
+fn display_folder(folder: &Path) -> String {
+    if folder.as_os_str().is_empty() {
+        return "the root of the store".to_string();
+    }
+    folder.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(gpg_ids: &[&str]) -> Vec<String> {
+        gpg_ids.iter().map(|gpg_id| gpg_id.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_gpg_ids() {
+        let contents = "# team\n alice@example.com \n\nbob@example.com\n  # former\n";
+        assert_eq!(parse(contents), ids(&["alice@example.com", "bob@example.com"]));
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn adds_missing_gpg_ids() {
+        let (contents, added) = add("# team\nalice@example.com", &ids(&["bob@example.com", "alice@example.com", "bob@example.com"]));
+        assert_eq!(contents, "# team\nalice@example.com\nbob@example.com\n");
+        assert_eq!(added, ids(&["bob@example.com"]));
+
+        let (contents, added) = add("", &ids(&["alice@example.com"]));
+        assert_eq!(contents, "alice@example.com\n");
+        assert_eq!(added, ids(&["alice@example.com"]));
+    }
+
+    #[test]
+    fn removes_gpg_ids() {
+        let contents = "# team\nalice@example.com\n  bob@example.com\ncarol@example.com\n";
+        assert_eq!(remove(contents, &ids(&["bob@example.com", "dave@example.com"])), "# team\nalice@example.com\ncarol@example.com\n");
+        assert_eq!(remove(contents, &ids(&["# team"])), "alice@example.com\n  bob@example.com\ncarol@example.com\n");
+        assert_eq!(remove("alice@example.com", &ids(&[])), "alice@example.com\n");
+    }
+}
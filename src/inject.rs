@@ -0,0 +1,63 @@
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use error::GitCredError;
+use error::Result;
+use store::Store;
+use transfer;
+
+/// A secret exposed to a command under `name`
+pub struct Injection {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Selects the secrets to expose to a command.
+///
+/// `maps` are `NAME=path` pairs, `folders` expose every secret under them with a name
+/// derived from the secret's path relative to the folder, e.g. `prod/db_password` with
+/// folder `prod` is exposed as `DB_PASSWORD`. Mappings take precedence over folders.
+pub fn select(store: &Store, maps: &[String], folders: &[String]) -> Result<Vec<Injection>> {
+    let mut injections: Vec<Injection> = Vec::new();
+    for folder in folders {
+        let folder = transfer::check_store_path(Path::new(folder), true)?;
+        for path in store.list_folder(&folder)? {
+            let name = variable_name(path.strip_prefix(&folder).unwrap());
+            injections.retain(|injection| injection.name != name);
+            injections.push(Injection { name, path });
+        }
+    }
+
+    for map in maps {
+        let mut parts = map.splitn(2, '=');
+        let name = parts.next().unwrap().to_string();
+        let path = match parts.next() {
+            Some(path) if !name.is_empty() && !path.is_empty() => transfer::check_store_path(Path::new(path), false)?,
+            _ => return Err(GitCredError::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid mapping, expected NAME=path: {}", map)))),
+        };
+        injections.retain(|injection| injection.name != name);
+        injections.push(Injection { name, path });
+    }
+    Ok(injections)
+}
+
+/* Uppercases the path and replaces everything that isn't alphanumeric with underscores */
+pub fn variable_name(path: &Path) -> String {
+    path.to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_variable_names() {
+        assert_eq!(variable_name(Path::new("api_key")), "API_KEY");
+        assert_eq!(variable_name(Path::new("db/prod-password.txt")), "DB_PROD_PASSWORD_TXT");
+        assert_eq!(variable_name(Path::new("aws/Key 2")), "AWS_KEY_2");
+        assert_eq!(variable_name(Path::new("caf\u{e9}")), "CAF_");
+    }
+}
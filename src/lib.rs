@@ -0,0 +1,52 @@
+extern crate age;
+extern crate git2;
+extern crate hmac;
+extern crate libc;
+extern crate reqwest;
+extern crate serde_json;
+extern crate sha2;
+extern crate signal_hook;
+#[cfg(feature = "native-pgp")]
+extern crate pgp;
+#[cfg(feature = "native-pgp")]
+extern crate rand;
+
+mod git_utils;
+mod git_config;
+mod command;
+mod age_backend;
+mod crypto;
+mod gpg;
+mod gpg_id;
+mod private_file;
+#[cfg(feature = "native-pgp")]
+mod native;
+mod encrypt;
+mod decrypt;
+mod dryrun;
+mod render;
+mod packets;
+mod preflight;
+mod recipients;
+mod reencrypt;
+mod resolver;
+mod github;
+mod error;
+mod store;
+mod transfer;
+#[cfg(test)]
+mod test_utils;
+
+pub mod audit;
+pub mod credential;
+pub mod filter;
+pub mod inject;
+pub mod merge;
+pub mod textconv;
+
+pub use error::GitCredError;
+pub use error::Result;
+pub use private_file::PrivateDir;
+pub use reencrypt::ReencryptOptions;
+pub use store::Store;
+pub use store::StoreEntry;
@@ -1,30 +1,50 @@
 extern crate git2;
-extern crate reqwest;
-extern crate serde_json;
-
-mod git_utils;
-mod git_config;
-mod command;
-mod gpg;
-mod encrypt;
-mod decrypt;
-mod resolver;
-mod util;
-mod github;
+extern crate git_cred;
+extern crate libc;
+extern crate rpassword;
+extern crate signal_hook;
 
 use git2::Repository;
+use git_cred::GitCredError;
+use git_cred::PrivateDir;
+use git_cred::ReencryptOptions;
+use git_cred::Result;
+use git_cred::Store;
+use signal_hook::consts::signal::SIGHUP;
+use signal_hook::consts::signal::SIGINT;
+use signal_hook::consts::signal::SIGQUIT;
+use signal_hook::consts::signal::SIGTERM;
+use signal_hook::iterator::Signals;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
-use util::error_out;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
 
+/* Exit code used for usage errors, errors from the store have their own exit codes */
+const USAGE_EXIT_CODE: i32 = 2;
 
-fn main() {
-    let repo_res = Repository::discover(".");
+fn error_out(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(USAGE_EXIT_CODE)
+}
 
-    if repo_res.is_err() {
-        panic!("Current directory isn't a git repo!");
-    }
+fn fail(err: GitCredError) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(err.exit_code())
+}
 
-    let repo = repo_res.unwrap();
+fn main() {
+    let repo = match Repository::discover(".") {
+        Ok(repo) => repo,
+        Err(err) => {
+            eprintln!("Current directory isn't a git repo!");
+            fail(GitCredError::Git(err));
+        }
+    };
 
     let args: Vec<String> = std::env::args().collect();
 
@@ -32,69 +52,272 @@ fn main() {
         error_out("Please provide an command to perform on the credential store");
     }
 
+    /* only init creates the store up front, anything else that writes to it creates it when it does.
+     * git runs the filters and the credential helper in every repo, which mustn't end up with a store */
+    let store = if args[1] == "init" && !args.iter().any(|arg| arg == "--dry-run") {
+        match Store::open(&repo) {
+            Ok(store) => store,
+            Err(err) => fail(err),
+        }
+    } else {
+        Store::open_without_creating(&repo)
+    };
+
     let command = &args[1];
     let command_args = &args[2..];
 
-    match command.as_str() {
+    let res = match command.as_str() {
         "init" => {
-            handle_init(&repo, command_args);
+            handle_init(&store, command_args)
         }
         "encrypt" => {
-            handle_encrypt(&repo, command_args);
+            handle_encrypt(&store, command_args)
         }
         "reencrypt" => {
-            handle_reencrypt(&repo, command_args);
+            handle_reencrypt(&store, command_args)
         }
         "decrypt" => {
-            let path = Path::new(&command_args[0]);
-            println!("{}", decrypt::decrypt(&repo, path));
+            handle_decrypt(&store, command_args)
+        }
+        "recipients" => {
+            handle_recipients(&store, command_args)
         }
         "save-key" => {
-            handle_set_user_key(&repo, command_args);
+            handle_set_user_key(&store, command_args)
+        }
+        "credential" => {
+            handle_credential(&store, command_args)
+        }
+        "list" => {
+            handle_list(&store, command_args)
+        }
+        "mv" => {
+            handle_copy(&store, command_args, true)
+        }
+        "cp" => {
+            handle_copy(&store, command_args, false)
+        }
+        "rm" => {
+            handle_remove(&store, command_args)
+        }
+        "edit" => {
+            handle_edit(&store, command_args)
+        }
+        "exec" => {
+            handle_exec(&store, command_args)
+        }
+        "render" => {
+            handle_render(&store, command_args)
+        }
+        "filter" => {
+            handle_filter(&store, command_args)
+        }
+        "textconv" => {
+            handle_textconv(&store, command_args)
+        }
+        "merge-driver" => {
+            handle_merge_driver(&store, command_args)
+        }
+        "hook" => {
+            handle_hook(&store, command_args)
+        }
+        "check-staged" => {
+            handle_check_staged(&store, command_args)
+        }
+        "verify" => {
+            handle_verify(&store, command_args)
         }
         "help" => {
             print_help(command_args);
+            Ok(())
         }
         _ => {
             error_out(&format!("Command not recognized: {}", command));
         }
     };
+
+    if let Err(err) = res {
+        fail(err);
+    }
 }
 
-fn handle_init(repo: &Repository, args: &[String]) {
-    if args.len() == 0 {
-        git_utils::get_credentials_dir(&repo);
-        return;
+fn handle_init(store: &Store, args: &[String]) -> Result<()> {
+    let mut options = ReencryptOptions::default();
+    let mut initialize = false;
+    let mut subfolder = String::new();
+    let mut backend = None;
+    let mut gpg_ids = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        if let Some(consumed) = parse_reencrypt_option(args, i, &mut options) {
+            i += consumed;
+            continue;
+        }
+        match args[i].as_str() {
+            "--redact" => {
+                i += 1;
+            }
+            "-f" => {
+                if i + 1 >= args.len() {
+                    error_out("Please provide folder to init '-f' flag");
+                }
+                subfolder = args[i + 1].clone();
+                initialize = true;
+                i += 2;
+            }
+            "--backend" => {
+                if i + 1 >= args.len() {
+                    error_out("Please provide a backend (gpg, age or native) to the '--backend' flag");
+                }
+                backend = Some(args[i + 1].clone());
+                initialize = true;
+                i += 2;
+            }
+            _ => {
+                gpg_ids.push(args[i].clone());
+                initialize = true;
+                i += 1;
+            }
+        }
     }
 
-    if args[0] == "-f" {
-        if args.len() <= 1 {
-            error_out("Please provide folder to init '-f' flag");
+    /* without a folder or gpg ids there's nothing to init, the store itself is created when opened */
+    if initialize {
+        store.init(&subfolder, gpg_ids, backend.as_ref().map(|backend| backend.as_str()), &options)?;
+    }
+
+    /* the drivers are only set up once the store is, so a failed init leaves the git config alone */
+    if !options.dry_run {
+        let redact = args.iter().any(|arg| arg == "--redact");
+        git_cred::textconv::install(store, redact)?;
+        git_cred::merge::install(store)?;
+    }
+    Ok(())
+}
+
+fn handle_reencrypt(store: &Store, args: &[String]) -> Result<()> {
+    let mut options = ReencryptOptions::default();
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match parse_reencrypt_option(args, i, &mut options) {
+            Some(consumed) => i += consumed,
+            None if args[i].starts_with("-") => error_out(&format!("Unknown argument to reencrypt: {}", args[i])),
+            None => {
+                paths.push(args[i].as_str());
+                i += 1;
+            }
         }
-        let subfolder = &args[1];
-        git_utils::create_sub_dir(repo, &Path::new(subfolder), args[2..].to_vec());
-        let path_to_subfolder = git_utils::get_credentials_dir(repo).join(subfolder);
-        encrypt::reencrypt_folder(repo, &path_to_subfolder);
-        return;
     }
 
-    git_utils::create_sub_dir(repo, Path::new(""), args[0..].to_vec());
-    encrypt::reencrypt_folder(repo, &git_utils::get_credentials_dir(repo));
+    if paths.is_empty() {
+        return store.reencrypt(&options);
+    }
+    store.reencrypt_paths(&paths, &options)
 }
 
-fn handle_reencrypt(repo: &Repository, _: &[String]) {
-    encrypt::reencrypt_folder(repo, &git_utils::get_credentials_dir(repo));
+fn handle_recipients(store: &Store, args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        error_out("Please provide a recipients action: show, add or remove");
+    }
+
+    let mut options = ReencryptOptions::default();
+    let mut folder = String::new();
+    let mut gpg_ids = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        if let Some(consumed) = parse_reencrypt_option(args, i, &mut options) {
+            i += consumed;
+            continue;
+        }
+        match args[i].as_str() {
+            "-f" => {
+                if i + 1 >= args.len() {
+                    error_out("Please provide a folder to the '-f' flag");
+                }
+                folder = args[i + 1].clone();
+                i += 2;
+            }
+            _ => {
+                gpg_ids.push(args[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    match args[0].as_str() {
+        "show" => {
+            if let Some(gpg_id) = gpg_ids.first() {
+                error_out(&format!("Unknown argument to recipients show: {}", gpg_id));
+            }
+            for gpg_id in store.recipients_for_folder(&folder)? {
+                println!("{}", gpg_id);
+            }
+            let source = store.recipients_source(&folder)?;
+            if source != Path::new(&folder) {
+                let source_name = if source.as_os_str().is_empty() { "the root of the store".to_string() } else { source.display().to_string() };
+                eprintln!("(inherited from {})", source_name);
+            }
+            Ok(())
+        }
+        "add" | "remove" if gpg_ids.is_empty() => error_out(&format!("Please provide the gpg ids to {}", args[0])),
+        "add" => {
+            let added = store.add_recipients(&folder, &gpg_ids, &options)?;
+            for gpg_id in gpg_ids.iter().filter(|gpg_id| !added.contains(gpg_id)) {
+                eprintln!("{} is already a gpg id of the folder", gpg_id);
+            }
+            Ok(())
+        }
+        "remove" => store.remove_recipients(&folder, &gpg_ids, &options),
+        action => error_out(&format!("Unknown recipients action: {}, expected show, add or remove", action)),
+    }
 }
 
-fn handle_encrypt(repo: &Repository, args: &[String]) {
+/* Parses the reencrypt option at args[i], if it is one. Returns the number of arguments it took */
+fn parse_reencrypt_option(args: &[String], i: usize, options: &mut ReencryptOptions) -> Option<usize> {
+    match args[i].as_str() {
+        "--force" => {
+            options.force = true;
+            Some(1)
+        }
+        "--skip-inaccessible" => {
+            options.skip_inaccessible = true;
+            Some(1)
+        }
+        "--dry-run" => {
+            options.dry_run = true;
+            Some(1)
+        }
+        "-j" | "--jobs" => {
+            options.jobs = match args.get(i + 1).map(|jobs| jobs.parse::<usize>()) {
+                Some(Ok(jobs)) if jobs > 0 => jobs,
+                _ => error_out("'--jobs' flag requires a number of jobs greater than 0"),
+            };
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+fn handle_encrypt(store: &Store, args: &[String]) -> Result<()> {
     if args.len() == 0 {
         error_out("Please provide path to the desired encrypted file");
     }
 
-    let path = Path::new(&args[0]);
+    let path = &args[0];
 
     if args.len() <= 1 {
-        error_out("Please provide either a string or file to encrypt");
+        if !std::io::stdin().is_terminal() {
+            error_out("Please provide either a string or file to encrypt");
+        }
+        let contents = prompt_secret(path);
+        return store.encrypt(path, contents);
+    }
+
+    if args[1] == "-" {
+        let mut contents = Vec::new();
+        std::io::stdin().read_to_end(&mut contents)?;
+        return store.encrypt(path, contents);
     }
 
     if args[1] == "-f".to_string() {
@@ -102,27 +325,443 @@ fn handle_encrypt(repo: &Repository, args: &[String]) {
             error_out("'-f' flag requires a file name");
         }
         let file_name = &args[2];
-        encrypt::encrypt_file(&repo, path, &file_name);
+        store.encrypt_file(path, &file_name)
+    } else {
+        store.encrypt(path, args[1].clone())
+    }
+}
+
+/* Prompts twice for the secret without echoing it, until both entries match */
+fn prompt_secret(path: &str) -> String {
+    loop {
+        let first = rpassword::prompt_password(format!("Enter secret for {}: ", path));
+        let second = rpassword::prompt_password(format!("Retype secret for {}: ", path));
+        match (first, second) {
+            (Ok(first), Ok(second)) => {
+                if first == second {
+                    return first;
+                }
+                eprintln!("Entries don't match, please try again");
+            }
+            _ => error_out("Unable to read secret from the terminal"),
+        }
+    }
+}
+
+fn handle_decrypt(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() == 0 {
+        error_out("Please provide path to the file to decrypt");
+    }
+
+    let path = &args[0];
+
+    if args.len() > 1 {
+        if args[1] != "-o" && args[1] != "--output" {
+            error_out(&format!("Unknown argument to decrypt: {}", args[1]));
+        }
+        if args.len() <= 2 {
+            error_out("'--output' flag requires a file name");
+        }
+        return store.decrypt_to_file(path, &args[2]);
+    }
+
+    let contents = store.decrypt(path)?;
+    let stdout = std::io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(&contents)?;
+    handle.flush()?;
+    Ok(())
+}
+
+fn handle_render(store: &Store, args: &[String]) -> Result<()> {
+    let mut template_path = None;
+    let mut output = None;
+    let mut check = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-o" | "--output" => {
+                if i + 1 >= args.len() {
+                    error_out("'--output' flag requires a file name");
+                }
+                output = Some(&args[i + 1]);
+                i += 2;
+            }
+            "--check" => {
+                check = true;
+                i += 1;
+            }
+            _ if template_path.is_none() => {
+                template_path = Some(&args[i]);
+                i += 1;
+            }
+            _ => error_out(&format!("Unknown argument to render: {}", args[i])),
+        }
+    }
+
+    let template = match template_path {
+        Some(path) if path == "-" => {
+            let mut template = String::new();
+            std::io::stdin().read_to_string(&mut template)?;
+            template
+        }
+        Some(path) => std::fs::read_to_string(path)?,
+        None => error_out("Please provide the template to render"),
+    };
+
+    if check {
+        return store.check_template(&template);
+    }
+    if let Some(output) = output {
+        return store.render_to_file(&template, output);
+    }
+
+    let contents = store.render(&template)?;
+    let stdout = std::io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(&contents)?;
+    handle.flush()?;
+    Ok(())
+}
+
+fn handle_filter(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() == 0 {
+        error_out("Please provide a filter action: clean, smudge or install");
+    }
+
+    if args[0] == "install" {
+        return git_cred::filter::install(store, &args[1..]);
+    }
+    if args[0] != "clean" && args[0] != "smudge" {
+        error_out(&format!("Unknown filter action: {}", args[0]));
+    }
+    if args.len() < 2 {
+        error_out(&format!("Please provide the path of the file to {}", args[0]));
+    }
+
+    let path = Path::new(&args[1]);
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let contents = if args[0] == "clean" {
+        git_cred::filter::clean(store, path, &input)?
+    } else {
+        git_cred::filter::smudge(store, path, &input)?
+    };
+
+    let stdout = std::io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(&contents)?;
+    handle.flush()?;
+    Ok(())
+}
+
+fn handle_textconv(store: &Store, args: &[String]) -> Result<()> {
+    let redact = args.iter().any(|arg| arg == "--redact");
+    let path = match args.iter().find(|arg| *arg != "--redact") {
+        Some(path) => path,
+        None => error_out("Please provide the path of the file to convert"),
+    };
+
+    let contents = git_cred::textconv::textconv(store, Path::new(path), redact)?;
+    let stdout = std::io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(&contents)?;
+    handle.flush()?;
+    Ok(())
+}
+
+fn handle_merge_driver(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() != 4 {
+        error_out("Please provide the base, ours and theirs files and the path being merged");
+    }
+
+    let path = Path::new(&args[3]);
+    if !git_cred::merge::merge(store, Path::new(&args[0]), Path::new(&args[1]), Path::new(&args[2]), path)? {
+        eprintln!("git-cred: conflict in {}, the conflict markers were encrypted along with it", path.display());
+        match git_cred::merge::get_store_path(store, path)? {
+            Some(store_path) => eprintln!("git-cred: resolve them with: git cred edit {}", store_path.display()),
+            None => eprintln!("git-cred: resolve them in {} once it's checked out", path.display()),
+        }
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn handle_hook(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() == 0 || args[0] != "install" {
+        error_out("Please provide a hook action: install");
+    }
+    git_cred::audit::install_hook(store)
+}
+
+fn handle_check_staged(store: &Store, _: &[String]) -> Result<()> {
+    report_problems(git_cred::audit::check_staged(store)?)
+}
+
+fn handle_verify(store: &Store, args: &[String]) -> Result<()> {
+    let folder = match args.len() {
+        0 => "",
+        1 => &args[0],
+        _ => error_out(&format!("Unknown argument to verify: {}", args[1])),
+    };
+    report_problems(git_cred::audit::verify(store, folder)?)
+}
+
+fn report_problems(problems: Vec<(std::path::PathBuf, git_cred::audit::Problem)>) -> Result<()> {
+    for &(ref path, ref problem) in &problems {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    if !problems.is_empty() {
+        return Err(GitCredError::CheckFailed(problems.len()));
+    }
+    Ok(())
+}
+
+fn handle_credential(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() == 0 {
+        error_out("Please provide a credential helper action: get, store or erase");
+    }
+
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    git_cred::credential::run(store, &args[0], stdin.lock(), stdout.lock())
+}
+
+fn handle_list(store: &Store, args: &[String]) -> Result<()> {
+    let mut folder = "";
+    let mut show_recipients = false;
+    for arg in args {
+        if arg == "--recipients" || arg == "-r" {
+            show_recipients = true;
+        } else {
+            folder = arg;
+        }
+    }
+
+    let root_name = if folder.is_empty() { "." } else { folder };
+    println!("{}{}", root_name, recipients_label(store, Path::new(folder), show_recipients)?);
+    print_tree(store, Path::new(folder), "", show_recipients)
+}
+
+fn print_tree(store: &Store, folder: &Path, prefix: &str, show_recipients: bool) -> Result<()> {
+    let entries = store.entries(folder)?;
+    for (i, entry) in entries.iter().enumerate() {
+        let is_last = i == entries.len() - 1;
+        let connector = if is_last { "└── " } else { "├── " };
+        let name = entry.path.file_name().unwrap().to_string_lossy();
+        if entry.is_folder {
+            println!("{}{}{}/{}", prefix, connector, name, recipients_label(store, &entry.path, show_recipients)?);
+            let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            print_tree(store, &entry.path, &child_prefix, show_recipients)?;
+        } else {
+            println!("{}{}{}", prefix, connector, name);
+        }
+    }
+    Ok(())
+}
+
+fn recipients_label(store: &Store, folder: &Path, show_recipients: bool) -> Result<String> {
+    if !show_recipients {
+        return Ok(String::new());
+    }
+    Ok(format!("  [{}]", store.recipients_for_folder(folder)?.join(", ")))
+}
+
+fn handle_copy(store: &Store, args: &[String], is_move: bool) -> Result<()> {
+    let force = args.iter().any(|arg| arg == "--force");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--force").collect();
+    if paths.len() != 2 {
+        error_out("Please provide a source and a destination path");
+    }
+
+    let destination = if is_move {
+        store.rename(paths[0], paths[1], force)?
     } else {
-        encrypt::encrypt_string(&repo, path, args[1].clone());
+        store.copy(paths[0], paths[1], force)?
+    };
+    println!("{} {} to {}", if is_move { "Moved" } else { "Copied" }, paths[0], destination.display());
+    Ok(())
+}
+
+fn handle_remove(store: &Store, args: &[String]) -> Result<()> {
+    let recursive = args.iter().any(|arg| arg == "-r");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "-r").collect();
+    if paths.is_empty() {
+        error_out("Please provide a path to remove");
     }
+
+    for path in paths {
+        if !recursive && store.path()?.join(path).is_dir() {
+            error_out(&format!("{} is a folder, use -r to remove it", path));
+        }
+        store.remove(path)?;
+    }
+    Ok(())
 }
 
-fn handle_set_user_key(repo: &Repository, args: &[String]) {
+fn handle_edit(store: &Store, args: &[String]) -> Result<()> {
+    if args.len() == 0 {
+        error_out("Please provide path to the secret to edit");
+    }
+
+    /* checked before the editor opens, rather than failing to save the edits */
+    let path = store.check_path(&args[0])?;
+    let path = path.as_path();
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => error_out(&format!("Invalid path to edit: {}", path.display())),
+    };
+
+    let contents = if store.path()?.join(path).is_file() {
+        store.decrypt(path)?
+    } else {
+        Vec::new()
+    };
+
+    /* dropping the directory wipes the plaintext, whatever happens below */
+    let private_dir = PrivateDir::new()?;
+    let plaintext_file = private_dir.write_file(&file_name, &contents)?;
+
+    let editor = std::env::var("VISUAL")
+        .or(std::env::var("EDITOR"))
+        .unwrap_or("vi".to_string());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&plaintext_file)
+        .status()?;
+    if !status.success() {
+        return Err(GitCredError::Io(std::io::Error::other(format!("Editor exited with {}, {} left unchanged", status, path.display()))));
+    }
+
+    let new_contents = std::fs::read(&plaintext_file)?;
+    if new_contents == contents {
+        println!("No changes to {}", path.display());
+        return Ok(());
+    }
+    if let Err(err) = store.encrypt(path, new_contents) {
+        /* the edits only exist in the plaintext file, keep it rather than wiping it */
+        private_dir.keep();
+        eprintln!("Unable to save your changes to {}, they were kept in {}, remove it once you saved them", path.display(), plaintext_file.display());
+        return Err(err);
+    }
+    Ok(())
+}
+
+/* Splits `[--files] [--map NAME=path]... [--folder folder]... -- command...` into (maps, folders, files, command) */
+fn parse_injection_args<'a>(args: &'a [String], command_name: &str) -> (Vec<String>, Vec<String>, bool, &'a [String]) {
+    let mut maps = Vec::new();
+    let mut folders = Vec::new();
+    let mut files = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--" => {
+                if i + 1 >= args.len() {
+                    error_out(&format!("Please provide a command to run after '--' to {}", command_name));
+                }
+                return (maps, folders, files, &args[i + 1..]);
+            }
+            "--files" => {
+                files = true;
+                i += 1;
+            }
+            "--map" | "--folder" => {
+                if i + 1 >= args.len() {
+                    error_out(&format!("'{}' flag requires a value", args[i]));
+                }
+                if args[i] == "--map" {
+                    maps.push(args[i + 1].clone());
+                } else {
+                    folders.push(args[i + 1].clone());
+                }
+                i += 2;
+            }
+            _ => error_out(&format!("Unknown argument to {}: {}, the command to run goes after '--'", command_name, args[i])),
+        }
+    }
+    error_out(&format!("Please provide a command to run after '--' to {}", command_name))
+}
+
+fn handle_exec(store: &Store, args: &[String]) -> Result<()> {
+    let (maps, folders, files, command) = parse_injection_args(args, "exec");
+
+    let mut child = Command::new(&command[0]);
+    child.args(&command[1..]);
+    let injections = git_cred::inject::select(store, &maps, &folders)?;
+    if files {
+        return exec_with_files(store, child, &injections);
+    }
+
+    for injection in injections {
+        let value = std::ffi::OsString::from_vec(store.decrypt(&injection.path)?);
+        child.env(&injection.name, value);
+    }
+
+    /* replacing this process with the command means signals go straight to the command
+     * and its exit status is ours. exec only returns on failure */
+    let err = child.exec();
+    Err(GitCredError::Io(std::io::Error::new(err.kind(), format!("Unable to run {}: {}", command[0], err))))
+}
+
+/* Runs the command with the secrets decrypted into a private directory and GIT_CRED_FILE_<NAME>
+ * pointing at them. The directory is wiped once the command exits, signals asking git-cred to
+ * stop are forwarded to the command so that it exits first */
+fn exec_with_files(store: &Store, mut child: Command, injections: &[git_cred::inject::Injection]) -> Result<()> {
+    let dir = PrivateDir::new()?;
+    for injection in injections {
+        let path = dir.write_file(&injection.path.to_string_lossy(), &store.decrypt(&injection.path)?)?;
+        child.env(format!("GIT_CRED_FILE_{}", injection.name), path);
+    }
+
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGQUIT, SIGTERM])?;
+    let signals_handle = signals.handle();
+    let status = match child.spawn() {
+        Ok(mut process) => {
+            let pid = process.id() as libc::pid_t;
+            let forwarder = std::thread::spawn(move || {
+                for signal in signals.forever() {
+                    unsafe { libc::kill(pid, signal); }
+                }
+            });
+            let status = process.wait();
+            signals_handle.close();
+            forwarder.join().unwrap();
+            status?
+        }
+        Err(err) => {
+            let program = child.get_program().to_string_lossy().into_owned();
+            return Err(GitCredError::Io(std::io::Error::new(err.kind(), format!("Unable to run {}: {}", program, err))));
+        }
+    };
+
+    /* exiting skips destructors, so the directory has to be wiped first */
+    dir.wipe()?;
+    std::process::exit(match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    });
+}
+
+fn handle_set_user_key(store: &Store, args: &[String]) -> Result<()> {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != "--dry-run").collect();
     if args.len() == 0 {
         error_out("Please provide a username/email/keyid to save-key");
     }
 
-    let uid = &args[0];
+    let uid = args[0];
 
     if args.len() == 1 {
-        let key_id = resolver::resolve_name(repo, uid);
-        let pub_key = gpg::export_key(&key_id);
-        resolver::set_key(repo, uid, &pub_key.unwrap());
-        return;
+        if dry_run {
+            return store.save_key_dry_run(uid, None);
+        }
+        return store.save_key(uid, None);
     }
 
-    let filename = &args[1];
+    let filename = args[1];
     let file_contents = std::fs::read_to_string(filename);
     if file_contents.is_err() {
         error_out(&format!("Unable to read public key from file: {}", filename));
@@ -130,7 +769,10 @@ fn handle_set_user_key(repo: &Repository, args: &[String]) {
 
     let pub_key = file_contents.unwrap();
 
-    resolver::set_key(repo, uid, &pub_key);
+    if dry_run {
+        return store.save_key_dry_run(uid, Some(&pub_key));
+    }
+    store.save_key(uid, Some(&pub_key))
 }
 
 fn print_help(args: &[String]) {
@@ -141,7 +783,22 @@ fn print_help(args: &[String]) {
             encrypt: encrypt a file or string in the store
             decrypt: decrypt a file in the store
             reencrypt: reencrypt a folder (or the whole store) with new gpg ids
+            recipients: show, add or remove the gpg ids of a folder
             save-key: save a key in the repo for other users to use
+            credential: act as a git credential helper
+            list: show the secrets in the store (or a folder) as a tree
+            mv: move a secret or folder, reencrypting it if needed
+            cp: copy a secret or folder, reencrypting it if needed
+            rm: remove a secret or folder
+            edit: edit a secret in your editor
+            exec: run a command with secrets as environment variables
+            render: fill in a template with secrets
+            filter: keep files encrypted in the repo at their natural paths
+            textconv: print the plaintext of an encrypted file, used by git diff
+            merge-driver: merge concurrent changes to an encrypted file, used by git merge
+            hook: install a pre-commit hook running check-staged
+            check-staged: check the staged files of the store are encrypted for their recipients
+            verify: check every file of the store is encrypted for its recipients
         
         To see more detailed instructions for each subcommand
             run: git cred help [subcommand]
@@ -157,39 +814,74 @@ fn print_help(args: &[String]) {
 "git cred init help
 Init a credential store or subfolder within the store
 
-usage: git cred init [-f <folder_name>] [<gpg_id>...]
+usage: git cred init [-f <folder_name>] [--backend <backend>] [--redact] [--force] [--skip-inaccessible] [--jobs <jobs>] [--dry-run] [<gpg_id>...]
     -f folder_name: instead of intializing the root folder of the credential store,
                     intialize a subfolder.
+
+    --backend backend:  encrypt the folder (and its subfolders) with the given backend:
+                    gpg, age, or native. Defaults to the creds.backend git config, or gpg.
+
+    --redact:       make git diff only show which lines of secrets changed, see textconv.
+                    Once set, later calls to init keep it even without --redact
+
+    --force:        reencrypt every file in the folder, even the ones already encrypted for
+                    the new gpg ids
+
+    --skip-inaccessible:    leave the files you can't decrypt as they are instead of failing
+
+    -j, --jobs jobs:    number of files to reencrypt at the same time, one per CPU by default
+
+    --dry-run:      print the old and new gpg ids of each folder, how they resolve, and the
+                    lock files and files that would change, without changing anything
     
     [gpg_ids...]:   a space separated list of gpg ids to use for encryption.
                     These gpgs will be specific to that folder
                     (or root folder if none specified). These can be emails, gpg key ids, or github usernames
                     if no gpgs are provided, the field of user.email in your git config is used.
+                    For the age backend, these can be age public keys, ssh public keys
+                    (ssh-ed25519 or ssh-rsa), or github usernames whose ssh keys are used.
 
 Notes:
     If the folder you provide (or the root folder) already exists, the existing gpg ids
     will be overwritten and the target folder will be automatically reencrypted with the provided gpg_ids
+    Before reencrypting, init checks that you can decrypt every file to reencrypt and that
+    the gpg ids resolve, and lists the blocking files if not, see reencrypt.
+    If reencrypting fails or you hit Ctrl-C, the gpg ids, backend and files of the folder
+    are left as they were. Keys imported and lock files written while resolving the gpg ids
+    are kept.
+    init also sets up git diff and git log -p to show the plaintext of the secrets you can
+    decrypt, in .gitattributes and the diff.cred.textconv git config, and git merge to merge
+    concurrent changes to secrets with merge-driver
 
 Examples:
     git cred init
+    git cred init --redact
     git cred init -f foo/bar
     git cred init username1
     git cred init username1 email@email.com
     git cred init -f foo username1 email@email.com
+    git cred init -f foo --backend age username1 \"ssh-ed25519 AAAA...\"
 "
         }
         "encrypt" => {
 "git cred encrypt help
 Encrypt a file or string in the store
 
-usage: git cred encrypt <path_to_encrypt> (-f <file_name> | <string_to_encrypt>)
+usage: git cred encrypt <path_to_encrypt> [-f <file_name> | - | <string_to_encrypt>]
     path_to_encrypt:    the location in the credential store you want to encrypt to
                         e.g. /foo/bar
     -f file_name:       you may provide a file that will be encrypted via this flag
+    -:                  read the contents to encrypt from standard in
     string_to_encrypt:  instead of providing a file, you can simply write the string to encrypt
-                        as a single command line argument
+                        as a single command line argument. Note that it will be visible in
+                        your shell history and to other users via ps
+
+    If nothing is provided to encrypt on a terminal, you will be prompted for the secret twice,
+    without echo
 
 Examples:
+    git cred encrypt foo
+    git cred encrypt foo - < secret.txt
     git cred encrypt foo hello
     git cred encrypt foo \"hello, world!\"
     git cred encrypt foo/bar hello
@@ -199,28 +891,63 @@ Examples:
 "git cred decrypt help
 Decrypt a file in the store
 
-usage: git cred decrypt <file_path>
-    file_path:  the file path to decrypt. The decrypted string will be output to standard out
+usage: git cred decrypt <file_path> [-o <output_file>]
+    file_path:      the file path to decrypt. The decrypted contents will be written to standard out
+                    as is, without a trailing newline
+    -o output_file: write the decrypted contents to output_file instead, the file is created
+                    with permissions 0600. Also available as --output
 
 Examples:
     git cred decrypt foo
-    git cred decrypt foo/bar"
+    git cred decrypt foo/bar
+    git cred decrypt certs/client.p12 -o client.p12"
         }
         "reencrypt" => {
 "git cred reencrypt help
 Reencrypt your credential store based on the gpg ids already present in the store.
 To reencrypt with different gpg ids, use 'git cred init' instead.
 
-usage:  git cred reencrypt
+usage:  git cred reencrypt [--force] [--skip-inaccessible] [--jobs <jobs>] [--dry-run] [<path>...]
+    --force:        reencrypt every file, even the ones already encrypted for their recipients
+    --skip-inaccessible:    leave the files you can't decrypt as they are instead of failing
+    -j, --jobs jobs:    number of files to reencrypt at the same time, one per CPU by default
+    --dry-run:      print the gpg ids of each folder, how they resolve, and the lock files
+                    and files that would change, without changing anything
+    [path...]:      only reencrypt these files and the files in these folders, instead of
+                    the whole store
+
+Notes:
+    Files are encrypted for the gpg ids of their folder, inherited from the parent folders
+    without a .gpg_id even when reencrypting only some paths. You only need to be able to
+    decrypt the files being reencrypted.
+    Files already encrypted for exactly the recipients of their folder are left as is, so
+    their ciphertext doesn't change. age files can't be checked this way and are always
+    reencrypted.
+    The gpg ids of each folder are resolved once, and the reencrypted files are listed in
+    order regardless of the number of jobs.
+    Before changing anything, every file to reencrypt is checked: you must be able to decrypt
+    it and the gpg ids of its folder must resolve. Otherwise the blocking files are listed
+    and nothing is changed, unless they're only files you can't decrypt and you passed
+    --skip-inaccessible.
+    The new ciphertexts are written to a staging folder in .git first, and only replace the
+    current ones once they were all written. If anything fails or you hit Ctrl-C, no file
+    is changed, but keys imported and lock files written while resolving the gpg ids are
+    kept. If a file can't be put back, its previous ciphertext is left in the staging folder
+    and its path is printed.
 
 Examples:
-    git cred reencrypt"
+    git cred reencrypt
+    git cred reencrypt --force
+    git cred reencrypt --jobs 4
+    git cred reencrypt --skip-inaccessible
+    git cred reencrypt --dry-run
+    git cred reencrypt team/ops shared/api_key"
         }
         "save-key" => {
 "git cred save-key
 Store a public key from your gpg keyring/github into the repo
 
-usage: git cred save-key <uid> [keyfile]
+usage: git cred save-key [--dry-run] <uid> [keyfile]
     uid:        Either a github username, email, or gpg key id
                 The key for that uid will be looked up and the public key will
                 be inserted into the repo for other users to encrypt with
@@ -228,6 +955,8 @@ usage: git cred save-key <uid> [keyfile]
                 the provided keyfile will be saved instead. All further encryption
                 for that uid will use that key instead. You may remove the keyfile
                 after this command completes
+    --dry-run:  print the key id and fingerprint of the key, and the key and lock files
+                that would be written, without changing the store or your keyring
 
 Notes:
     This function can be useful to set the desired key if a given email has more than
@@ -238,6 +967,293 @@ Examples:
     git cred save-key AAABBBCCC
     git cred save-key email@email.com
     git cred save-key email@email.com /path/to/keyfile.asc
+    git cred save-key --dry-run username1
+"
+        }
+        "list" => {
+"git cred list
+Show the secrets in the store as a tree, dot-entries (.gpg_id, .keys...) are skipped
+
+usage: git cred list [--recipients] [folder]
+    folder:         only show the secrets under this folder
+    --recipients:   also show the gpg ids that each folder is encrypted for, taking
+                    the .gpg_id files of parent folders into account. Also available as -r
+
+Examples:
+    git cred list
+    git cred list special
+    git cred list --recipients
+"
+        }
+        "mv" | "cp" => {
+"git cred mv / git cred cp
+Move or copy a secret or folder within the store
+
+usage: git cred mv [--force] <source> <destination>
+       git cred cp [--force] <source> <destination>
+    source:         the secret or folder to move or copy. Folders are moved along with
+                    their .gpg_id files
+    destination:    the new path. If it is an existing folder, the source is moved into it
+    --force:        overwrite the destination if it already exists
+
+Notes:
+    If the recipients of the destination differ from the recipients of the source
+    (e.g. when moving a secret into a restricted subfolder), the moved files are
+    automatically reencrypted for the recipients of the destination.
+
+Examples:
+    git cred mv api_key special/api_key
+    git cred mv api_key special
+    git cred cp --force staging/db prod/db
+"
+        }
+        "rm" => {
+"git cred rm
+Remove secrets from the store
+
+usage: git cred rm [-r] <path>...
+    path:   the secrets to remove
+    -r:     allow removing folders and everything in them
+
+Examples:
+    git cred rm api_key
+    git cred rm -r old_folder
+"
+        }
+        "edit" => {
+"git cred edit
+Edit a secret in your editor
+
+usage: git cred edit <path>
+    path:   the secret to edit, it is created if it doesn't exist yet
+
+Notes:
+    The secret is decrypted into a file only readable by you, in a private directory
+    under /dev/shm when available (so the plaintext never reaches the disk), and opened
+    with $VISUAL, $EDITOR or vi. The secret is only reencrypted if it changed.
+    The decrypted file is overwritten and deleted afterwards, even if the editor fails.
+    Only if reencrypting your changes fails is it kept, and its path printed, so that your
+    edits aren't lost.
+
+Examples:
+    git cred edit prod/config.yml
+    EDITOR=nano git cred edit api_key
+"
+        }
+        "exec" => {
+"git cred exec
+Run a command with secrets from the store as environment variables
+
+usage: git cred exec [--files] [--map <NAME=path>]... [--folder <folder>]... -- <command>...
+    --files:            decrypt the secrets into files instead, and set GIT_CRED_FILE_<NAME> to
+                        the path of each file
+    --map NAME=path:    set the environment variable NAME to the decrypted secret at path
+    --folder folder:    set an environment variable for every secret under folder, named after
+                        the secret's path in the folder: uppercased, with anything that isn't
+                        a letter or a digit replaced by '_' (e.g. prod/db-password -> DB_PASSWORD)
+    command:            the command to run, after '--'
+
+Notes:
+    The variables are only set in the environment of the command, not in your shell.
+    git-cred replaces itself with the command, so signals are received by the command
+    directly and the exit status is the command's.
+    With --files, the files are written to a directory only you can access, on /dev/shm
+    when possible so they never reach the disk. git-cred waits for the command, forwarding
+    SIGHUP, SIGINT, SIGQUIT and SIGTERM to it, then wipes the directory and exits with the
+    command's exit status.
+
+Examples:
+    git cred exec --map DB_PASS=prod/db -- ./deploy.sh
+    git cred exec --folder prod -- ./deploy.sh
+    git cred exec --folder prod --map TOKEN=ci/token -- sh -c 'echo $TOKEN | login'
+    git cred exec --files --map TLS_KEY=prod/tls.key -- ./server.sh
+"
+        }
+        "render" => {
+"git cred render
+Fill in a template with secrets from the store
+
+usage: git cred render <template> [-o <output_file>] [--check]
+    template:           the template file, or '-' to read it from standard in. Every
+                        {{ cred \"path\" }} placeholder is replaced with the decrypted secret at path,
+                        as is. Anything else is left untouched
+    -o output_file:     write the result to output_file, only readable by you, instead of
+                        standard out
+    --check:            only check that every placeholder references a secret in the store,
+                        nothing is decrypted
+
+Notes:
+    If a placeholder is malformed or references a secret that doesn't exist, nothing is
+    written and every problem is reported along with its line in the template.
+
+Examples:
+    git cred render config.yml.tmpl -o config.yml
+    git cred render --check config.yml.tmpl
+    git cred render - < config.yml.tmpl | kubectl apply -f -
+"
+        }
+        "filter" => {
+"git cred filter
+Keep files encrypted in the repo at their natural paths, with git clean/smudge filters
+
+usage: git cred filter install [patterns...]
+       git cred filter clean|smudge <file_path>
+    install:            register the filter and the merge driver in the git config of the
+                        repo, and add '<pattern> filter=cred' and '<pattern> merge=cred'
+                        lines to .gitattributes for each pattern
+    clean:              encrypt standard in to standard out, used by git when staging a file
+    smudge:             decrypt standard in to standard out, used by git when checking out a file
+    file_path:          the path of the file, relative to the top of the repo
+
+Notes:
+    Files are encrypted for whoever a secret at the same path in the credential store would
+    be encrypted for, e.g. config/secrets.yml uses the .gpg_id of
+    .credential-store/config if there is one, or the root .gpg_id otherwise.
+    Files you can't decrypt are checked out encrypted.
+    The filter has to be installed in every clone of the repo, run install without patterns
+    to only register it.
+    Unchanged files keep their ciphertext, to reencrypt a file after its recipients changed,
+    run: git rm --cached <file> && git add <file>
+
+Examples:
+    git cred filter install config/secrets.yml '*.key'
+    git cred filter install
+"
+        }
+        "textconv" => {
+"git cred textconv
+Print the plaintext of an encrypted file, used by git diff and git log -p
+
+usage: git cred textconv [--redact] <file_path>
+    --redact:           print a hash of each line instead of the line, so only which lines
+                        changed is shown. The hashes are keyed with a random key kept in
+                        .git/cred-redact-key, so they can't be checked against guesses
+    file_path:          the encrypted file
+
+Notes:
+    init sets this up for the credential store, in .gitattributes and the diff.cred.textconv
+    git config. Files you can't decrypt are shown as a single placeholder line.
+
+Examples:
+    git cred textconv .credential-store/foo
+    git config diff.cred.textconv \"git cred textconv --redact\"
+"
+        }
+        "merge-driver" => {
+"git cred merge-driver
+Merge concurrent changes to an encrypted file, used by git merge
+
+usage: git cred merge-driver <base> <ours> <theirs> <file_path>
+    base, ours, theirs: the encrypted versions of the file, the result is written to ours
+    file_path:          the path of the file being merged, relative to the top of the repo
+
+Notes:
+    init sets this up for the credential store, and filter install for filtered files, in
+    .gitattributes and the merge.cred.driver git config.
+    The three versions are decrypted and merged line by line, and the result is encrypted
+    for the current recipients of the file. If the changes conflict, the result is encrypted
+    with the conflict markers in it and git reports the file as conflicted: resolve them with
+    git cred edit, then git add the file.
+
+Examples:
+    git config merge.cred.driver \"git cred merge-driver %O %A %B %P\"
+"
+        }
+        "hook" | "check-staged" => {
+"git cred hook | check-staged
+Stop plaintext or wrongly encrypted secrets from being committed
+
+usage: git cred hook install
+       git cred check-staged
+    hook install:       install a pre-commit hook running check-staged
+    check-staged:       check every staged file of the store, except .gpg_id, .backend and
+                        .keys, is encrypted for exactly the recipients of its folder
+
+Notes:
+    check-staged reads the recipient key ids of the staged files without decrypting them, and
+    compares them with the staged .gpg_id and .backend files.
+    age files can't be checked this way, only their number of recipients is compared.
+    Each problem is reported with the path of the file in the store, and check-staged exits
+    with exit code 12 if there are any.
+    hook install won't overwrite an existing pre-commit hook, add git cred check-staged to it
+    instead.
+
+Examples:
+    git cred hook install
+    git cred check-staged
+"
+        }
+        "recipients" => {
+"git cred recipients
+Show, add or remove the gpg ids of a folder of the credential store
+
+usage: git cred recipients show [-f <folder_name>]
+       git cred recipients add [-f <folder_name>] [--force] [--skip-inaccessible] [--jobs <jobs>] [--dry-run] <gpg_id>...
+       git cred recipients remove [-f <folder_name>] [--force] [--skip-inaccessible] [--jobs <jobs>] [--dry-run] <gpg_id>...
+    -f folder_name: the folder whose gpg ids to show or change, the root folder by default
+    gpg_ids:        the gpg ids to add or remove, see init
+    --force, --skip-inaccessible, --jobs, --dry-run: see reencrypt
+
+Notes:
+    show prints the gpg ids of the folder, one per line, and on stderr the folder they're
+    inherited from if the folder doesn't have a .gpg_id file.
+    add and remove edit the .gpg_id file of the folder in place, keeping its other gpg ids
+    and comments (lines starting with #), and then reencrypt the folder and its subfolders.
+    A folder without a .gpg_id file gets one with the gpg ids it inherited.
+    The added gpg ids are resolved before anything changes, and if anything fails the
+    .gpg_id file and the files of the folder are left as they were.
+    remove refuses to remove the last gpg id of a folder.
+
+Examples:
+    git cred recipients show -f prod
+    git cred recipients add -f prod username1 email@email.com
+    git cred recipients remove -f prod username1
+    git cred recipients add --dry-run username2
+"
+        }
+        "verify" => {
+"git cred verify
+Check every file of the store is encrypted for exactly the recipients of its folder
+
+usage: git cred verify [<folder>]
+    folder:             only check the files under folder
+
+Notes:
+    The recipient key ids are read from the files without decrypting them, and compared to
+    the keys the gpg ids of their folder resolve to. Each file with missing or extra
+    recipients is reported, and verify exits with exit code 12 if there are any, e.g. after a
+    .gpg_id was edited without running reencrypt. Files whose gpg ids can't be resolved are
+    reported too, and the other files still get checked. verify doesn't import keys or write
+    lock files.
+    age files can't be checked this way, only their number of recipients is compared.
+
+Examples:
+    git cred verify
+    git cred verify prod
+"
+        }
+        "credential" => {
+"git cred credential
+Act as a git credential helper, storing credentials in the credential store
+
+usage: git cred credential (get | store | erase)
+    Reads the credential description from standard in and answers on standard out,
+    following git's credential helper protocol. Credentials are stored at
+    <folder>/<protocol>/<host>[/<path>]/<username> in the credential store, where
+    folder is the creds.helperfolder git config (git-credentials by default).
+
+Notes:
+    To use git-cred as the credential helper of a repo, run:
+        git config credential.helper cred
+    which calls the git-credential-cred binary installed alongside git-cred.
+    Only the people the credential folder is encrypted for can read the credentials.
+    Only the password, and its password_expiry_utc and oauth_refresh_token if git sends
+    them, are stored.
+
+Examples:
+    git config credential.helper cred
+    git config creds.helperfolder mirrors/credentials
+    printf 'protocol=https\\nhost=example.com\\n' | git cred credential get
 "
         }
         _ => {
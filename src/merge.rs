@@ -0,0 +1,157 @@
+use git2;
+use git2::ConfigLevel;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Output;
+use command;
+use crypto;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use private_file::PrivateDir;
+use store::Store;
+
+const DRIVER_KEY_NAME: &'static str = "merge.cred.driver";
+const NAME_KEY_NAME: &'static str = "merge.cred.name";
+
+/// Git merge driver: decrypts the base, ours and theirs versions of the file at `path` (relative
+/// to the top of the repo), merges them line by line and encrypts the result into `ours` for the
+/// current recipients of the path.
+///
+/// Returns false if the merge had conflicts, the result is then encrypted with the conflict markers
+/// in it, so that the plaintext never ends up in the working tree.
+pub fn merge(store: &Store, base: &Path, ours: &Path, theirs: &Path, path: &Path) -> Result<bool> {
+    let repo = store.repo();
+    let dir = PrivateDir::new()?;
+    let base_plaintext = dir.write_file("base", &decrypt_version(store, base)?)?;
+    let ours_plaintext = dir.write_file("ours", &decrypt_version(store, ours)?)?;
+    let theirs_plaintext = dir.write_file("theirs", &decrypt_version(store, theirs)?)?;
+
+    let paths: Vec<String> = [&ours_plaintext, &base_plaintext, &theirs_plaintext].iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let output = command::run_command("git", vec!["merge-file", "-p", "-L", "ours", "-L", "base", "-L", "theirs", &paths[0], &paths[1], &paths[2]])?;
+
+    let conflicts = count_conflicts(&output)?;
+
+    /* files kept encrypted elsewhere by the filter use the recipients of the same path in the store */
+    let store_path = get_store_path(store, path)?.unwrap_or(path.to_path_buf());
+    encrypt::encrypt_bytes_as(repo, &store_path, ours, &output.stdout)?;
+    Ok(conflicts == 0)
+}
+
+/// Registers the merge driver in the repo's git config and marks the secrets of the store
+/// as using it in `.gitattributes`
+pub fn install(store: &Store) -> Result<()> {
+    let repo = store.repo();
+    let mut config = repo.config()?.open_level(ConfigLevel::Local)?;
+    config.set_str(NAME_KEY_NAME, "git-cred merge driver for encrypted files")?;
+    config.set_str(DRIVER_KEY_NAME, "git cred merge-driver %O %A %B %P")?;
+    git_utils::add_store_attributes(repo, "merge")
+}
+
+/// Returns the path in the store of `path` (relative to the top of the repo), if it's in the store
+pub fn get_store_path(store: &Store, path: &Path) -> Result<Option<PathBuf>> {
+    let relative_store = git_utils::get_relative_credentials_dir(store.repo())?;
+    match path.strip_prefix(&relative_store) {
+        Ok(store_path) => Ok(Some(store_path.to_path_buf())),
+        Err(_) => Ok(None),
+    }
+}
+
+/* merge-file exits with the number of conflicts, or a negative number on errors */
+fn count_conflicts(output: &Output) -> Result<i32> {
+    match output.status.code() {
+        Some(code) if (0..128).contains(&code) => Ok(code),
+        _ => {
+            let message = format!("git merge-file failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            Err(GitCredError::Git(git2::Error::from_str(&message)))
+        }
+    }
+}
+
+/* A version missing on one side (e.g. the base of a file added on both branches) is empty */
+fn decrypt_version(store: &Store, path: &Path) -> Result<Vec<u8>> {
+    if fs::metadata(path)?.len() == 0 {
+        return Ok(Vec::new());
+    }
+    crypto::get_backend_for_ciphertext(store.repo(), path)?.decrypt(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use test_utils::TestRepo;
+    use super::*;
+
+    const BASE: &'static [u8] = b"user: me\npassword: hunter2\nhost: example.com\n";
+
+    fn output(status: i32) -> Output {
+        Output { status: ExitStatus::from_raw(status), stdout: Vec::new(), stderr: b"error: oops\n".to_vec() }
+    }
+
+    fn encrypted_version(test_repo: &TestRepo, name: &str, contents: &[u8]) -> PathBuf {
+        let path = test_repo.write_file(name, b"");
+        if !contents.is_empty() {
+            encrypt::encrypt_bytes_as(&test_repo.repo, Path::new("db"), &path, contents).unwrap();
+        }
+        path
+    }
+
+    /* Merges the three versions of .credential-store/db, returns whether it was clean and the merged plaintext */
+    fn merge_versions(base: &[u8], ours: &[u8], theirs: &[u8]) -> (bool, String) {
+        let test_repo = TestRepo::new();
+        let base = encrypted_version(&test_repo, "base", base);
+        let ours = encrypted_version(&test_repo, "ours", ours);
+        let theirs = encrypted_version(&test_repo, "theirs", theirs);
+
+        let store = test_repo.store();
+        let clean = merge(&store, &base, &ours, &theirs, Path::new(".credential-store/db")).unwrap();
+        let merged = crypto::get_backend_for_ciphertext(&test_repo.repo, &ours).unwrap().decrypt(&ours).unwrap();
+        (clean, String::from_utf8(merged).unwrap())
+    }
+
+    #[test]
+    fn counts_conflicts_from_the_exit_code() {
+        assert_eq!(count_conflicts(&output(0)).unwrap(), 0);
+        assert_eq!(count_conflicts(&output(3 << 8)).unwrap(), 3);
+        assert_eq!(count_conflicts(&output(127 << 8)).unwrap(), 127);
+    }
+
+    #[test]
+    fn fails_on_errors_and_signals() {
+        /* merge-file's -1 is exit code 255 */
+        assert!(count_conflicts(&output(255 << 8)).is_err());
+        assert!(count_conflicts(&output(128 << 8)).is_err());
+        /* killed by SIGKILL, there's no exit code */
+        assert!(count_conflicts(&output(9)).is_err());
+    }
+
+    #[test]
+    fn merges_changes_to_different_lines() {
+        let ours = b"user: you\npassword: hunter2\nhost: example.com\n";
+        let theirs = b"user: me\npassword: hunter2\nhost: example.org\n";
+        let (clean, merged) = merge_versions(BASE, ours, theirs);
+        assert!(clean);
+        assert_eq!(merged, "user: you\npassword: hunter2\nhost: example.org\n");
+    }
+
+    #[test]
+    fn keeps_conflicts_encrypted() {
+        let ours = b"user: me\npassword: hunter3\nhost: example.com\n";
+        let theirs = b"user: me\npassword: hunter4\nhost: example.com\n";
+        let (clean, merged) = merge_versions(BASE, ours, theirs);
+        assert!(!clean);
+        assert!(merged.contains("<<<<<<< ours\npassword: hunter3\n=======\npassword: hunter4\n>>>>>>> theirs\n"), "{}", merged);
+    }
+
+    #[test]
+    fn treats_a_missing_base_as_empty() {
+        let (clean, merged) = merge_versions(b"", b"password: hunter2\n", b"password: hunter2\n");
+        assert!(clean);
+        assert_eq!(merged, "password: hunter2\n");
+    }
+}
@@ -0,0 +1,260 @@
+use git2::Repository;
+use pgp::composed::Deserializable;
+use pgp::composed::Message;
+use pgp::composed::SignedPublicKey;
+use pgp::composed::SignedPublicSubKey;
+use pgp::composed::SignedSecretKey;
+use pgp::crypto::sym::SymmetricKeyAlgorithm;
+use pgp::errors::Error as PgpError;
+use pgp::ser::Serialize;
+use pgp::types::KeyTrait;
+use rand::thread_rng;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::path::PathBuf;
+use crypto::CryptoBackend;
+use error::GitCredError;
+use error::Result;
+use git_config;
+
+const SECRET_KEY_FOLDER_NAME: &'static str = "secret";
+const PASSPHRASE_ENV_NAME: &'static str = "GIT_CRED_PASSPHRASE";
+
+/* Pure rust OpenPGP backend, doesn't need gpg to be installed.
+ * Public keys are kept as armored files named after their fingerprint in the keyring folder
+ * (creds.keyring, ~/.git-cred/keyring by default).
+ * Secret keys are read from the "secret" subfolder of the keyring and from creds.secretkey,
+ * protected secret keys are unlocked with the GIT_CRED_PASSPHRASE environment variable */
+pub struct NativeBackend {
+    keyring: PathBuf,
+    secret_key_files: Vec<PathBuf>,
+}
+
+impl NativeBackend {
+    pub fn new(repo: &Repository) -> Result<NativeBackend> {
+        let keyring = match git_config::get_keyring_location(repo)? {
+            Some(location) => location,
+            None => {
+                let home = env::var("HOME").unwrap_or(String::new());
+                Path::new(&home).join(".git-cred").join("keyring")
+            }
+        };
+
+        let mut secret_key_files = Vec::new();
+        if let Some(secret_key) = git_config::get_secret_key_location(repo)? {
+            secret_key_files.push(secret_key);
+        }
+        /* the keyring is only created when a key gets imported, dry runs must not create it */
+        let secret_key_dir = keyring.join(SECRET_KEY_FOLDER_NAME);
+        if secret_key_dir.is_dir() {
+            for file in fs::read_dir(secret_key_dir)? {
+                let file_res = file?;
+                if file_res.file_type()?.is_file() {
+                    secret_key_files.push(file_res.path());
+                }
+            }
+        }
+
+        Ok(NativeBackend { keyring, secret_key_files })
+    }
+
+    fn public_keys(&self) -> Result<Vec<SignedPublicKey>> {
+        let mut keys = Vec::new();
+        if !self.keyring.is_dir() {
+            return Ok(keys);
+        }
+        for file in fs::read_dir(&self.keyring)? {
+            let file_res = file?;
+            if !file_res.file_type()?.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(file_res.path())?;
+            let (key, _) = SignedPublicKey::from_string(&contents)
+                .map_err(|err| crypto_error(&format!("read key {}", file_res.path().display()), err))?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    fn secret_keys(&self) -> Result<Vec<SignedSecretKey>> {
+        let mut keys = Vec::new();
+        for path in &self.secret_key_files {
+            let contents = fs::read_to_string(path)?;
+            let (key, _) = SignedSecretKey::from_string(&contents)
+                .map_err(|err| crypto_error(&format!("read secret key {}", path.display()), err))?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    fn find_key(&self, id: &str) -> Result<Option<SignedPublicKey>> {
+        for key in self.public_keys()? {
+            if key_matches(&key, id) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_encryption_subkey(&self, id: &str) -> Result<SignedPublicSubKey> {
+        let key = match self.find_key(id)? {
+            Some(key) => key,
+            None => return Err(GitCredError::UnresolvableRecipient(id.to_string())),
+        };
+
+        match key.public_subkeys.into_iter().find(|subkey| subkey.is_encryption_key()) {
+            Some(subkey) => Ok(subkey),
+            None => Err(GitCredError::Crypto {
+                operation: format!("encrypt for {}", id),
+                message: "key has no encryption subkey".to_string(),
+            }),
+        }
+    }
+}
+
+impl CryptoBackend for NativeBackend {
+    fn decrypt(&self, path: &Path) -> Result<Vec<u8>> {
+        let operation = format!("decrypt {}", path.display());
+        let contents = fs::read(path)?;
+        let message = if contents.starts_with(b"-----BEGIN") {
+            Message::from_armor_single(Cursor::new(contents)).map(|(message, _)| message)
+        } else {
+            Message::from_bytes(Cursor::new(contents))
+        }.map_err(|err| crypto_error(&operation, err))?;
+
+        let secret_keys = self.secret_keys()?;
+        let secret_key_refs: Vec<&SignedSecretKey> = secret_keys.iter().collect();
+        let passphrase = env::var(PASSPHRASE_ENV_NAME).unwrap_or(String::new());
+
+        let (mut decrypter, _) = message.decrypt(|| passphrase.clone(), &secret_key_refs)
+            .map_err(|err| crypto_error(&operation, err))?;
+        /* the decrypter yields the decrypted message, whose content is the plaintext */
+        let decrypted = match decrypter.next() {
+            Some(decrypted) => decrypted.map_err(|err| crypto_error(&operation, err))?,
+            None => return Err(GitCredError::Crypto { operation, message: "message has no content".to_string() }),
+        };
+        let plaintext = decrypted.get_content()
+            .map_err(|err| crypto_error(&operation, err))?;
+
+        match plaintext {
+            Some(bytes) => Ok(bytes),
+            None => Err(GitCredError::Crypto { operation, message: "message has no content".to_string() }),
+        }
+    }
+
+    fn encrypt(&self, path: &Path, contents: &[u8], recipients: &Vec<&str>) -> Result<()> {
+        let operation = format!("encrypt {}", path.display());
+        let mut subkeys = Vec::new();
+        for recipient in recipients {
+            subkeys.push(self.find_encryption_subkey(recipient)?);
+        }
+        let subkey_refs: Vec<&SignedPublicSubKey> = subkeys.iter().collect();
+
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(String::new());
+        let message = Message::new_literal_bytes(file_name.as_bytes(), contents);
+        let encrypted = message.encrypt_to_keys(&mut thread_rng(), SymmetricKeyAlgorithm::AES256, &subkey_refs)
+            .map_err(|err| crypto_error(&operation, err))?;
+        let bytes = encrypted.to_bytes().map_err(|err| crypto_error(&operation, err))?;
+
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn has_key(&self, id: &str) -> Result<bool> {
+        Ok(self.find_key(id)?.is_some())
+    }
+
+    fn get_key_id(&self, id: &str) -> Result<String> {
+        match self.find_key(id)? {
+            Some(key) => Ok(to_hex(&key.fingerprint())),
+            None => Err(GitCredError::UnresolvableRecipient(id.to_string())),
+        }
+    }
+
+    fn get_key_ids(&self, id: &str) -> Result<Vec<String>> {
+        match self.find_key(id)? {
+            Some(key) => {
+                let mut key_ids = vec![to_hex(key.key_id().as_ref())];
+                for subkey in &key.public_subkeys {
+                    key_ids.push(to_hex(subkey.key_id().as_ref()));
+                }
+                Ok(key_ids)
+            }
+            None => Err(GitCredError::UnresolvableRecipient(id.to_string())),
+        }
+    }
+
+    fn get_secret_key_ids(&self) -> Result<Option<Vec<String>>> {
+        let mut key_ids = Vec::new();
+        for key in self.secret_keys()? {
+            key_ids.push(to_hex(key.key_id().as_ref()));
+            for subkey in &key.secret_subkeys {
+                key_ids.push(to_hex(subkey.key_id().as_ref()));
+            }
+        }
+        Ok(Some(key_ids))
+    }
+
+    fn import_key(&self, key_contents: &str) -> Result<String> {
+        let (key, _) = SignedPublicKey::from_string(key_contents)
+            .map_err(|err| crypto_error("import key", err))?;
+        key.verify().map_err(|err| crypto_error("import key", err))?;
+
+        let key_id = to_hex(&key.fingerprint());
+        fs::create_dir_all(&self.keyring)?;
+        fs::write(self.keyring.join(format!("{}.asc", key_id)), key_contents)?;
+        Ok(key_id)
+    }
+
+    fn inspect_key(&self, key_contents: &str) -> Result<(String, Vec<String>)> {
+        let (key, _) = SignedPublicKey::from_string(key_contents)
+            .map_err(|err| crypto_error("read key", err))?;
+        let mut key_ids = vec![to_hex(key.key_id().as_ref())];
+        for subkey in &key.public_subkeys {
+            key_ids.push(to_hex(subkey.key_id().as_ref()));
+        }
+        Ok((to_hex(&key.fingerprint()), key_ids))
+    }
+
+    fn export_key(&self, id: &str) -> Result<String> {
+        match self.find_key(id)? {
+            Some(key) => key.to_armored_string(None).map_err(|err| crypto_error(&format!("export key {}", id), err)),
+            None => Err(GitCredError::UnresolvableRecipient(id.to_string())),
+        }
+    }
+}
+
+/* Matches fingerprints, long and short key ids of the key or its subkeys, and user ids.
+ * Like gpg, an id is only taken as a key id or fingerprint if it has 8, 16 or 40 hex digits */
+fn key_matches(key: &SignedPublicKey, id: &str) -> bool {
+    if let Some(key_id) = parse_key_id(id) {
+        return to_hex(&key.fingerprint()).ends_with(&key_id)
+            || key.public_subkeys.iter().any(|subkey| to_hex(&subkey.fingerprint()).ends_with(&key_id));
+    }
+
+    let lowercase_id = id.to_lowercase();
+    key.details.users.iter().any(|user| String::from_utf8_lossy(user.id.id()).to_lowercase().contains(&lowercase_id))
+}
+
+/* Returns the id in uppercase without spaces or 0x prefix if it's a short or long key id or a fingerprint */
+fn parse_key_id(id: &str) -> Option<String> {
+    let id = id.replace(" ", "");
+    let hex = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(&id);
+    match hex.len() {
+        8 | 16 | 40 if hex.chars().all(|c| c.is_ascii_hexdigit()) => Some(hex.to_uppercase()),
+        _ => None,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn crypto_error(operation: &str, err: PgpError) -> GitCredError {
+    GitCredError::Crypto {
+        operation: operation.to_string(),
+        message: err.to_string(),
+    }
+}
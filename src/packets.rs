@@ -0,0 +1,338 @@
+/* Reads who a message is encrypted to from its headers, without decrypting it */
+
+const AGE_HEADER: &'static [u8] = b"age-encryption.org/v1\n";
+const AGE_STANZA_PREFIX: &'static str = "-> ";
+const AGE_MAC_PREFIX: &'static str = "---";
+/* age adds a stanza with a random tag ending in this suffix to every file, it isn't a recipient */
+const AGE_GREASE_SUFFIX: &'static str = "-grease";
+
+const ARMOR_HEADER: &'static str = "-----BEGIN PGP MESSAGE-----";
+const ARMOR_FOOTER_PREFIX: &'static str = "-----";
+const ARMOR_CHECKSUM_PREFIX: &'static str = "=";
+
+const PKESK_TAG: u8 = 1;
+const SKESK_TAG: u8 = 3;
+const SED_TAG: u8 = 9;
+const SEIPD_TAG: u8 = 18;
+const AEAD_TAG: u8 = 20;
+
+/* Returns the long key ids (uppercase hex) from the PKESK packets of an encrypted OpenPGP message,
+ * binary or ASCII armored, or None if the contents aren't one. Anonymous recipients have a key id
+ * of all zeros */
+pub fn read_pgp_recipients(contents: &[u8]) -> Option<Vec<String>> {
+    if contents.starts_with(ARMOR_HEADER.as_bytes()) {
+        return read_binary_recipients(&dearmor(contents)?);
+    }
+    read_binary_recipients(contents)
+}
+
+fn read_binary_recipients(contents: &[u8]) -> Option<Vec<String>> {
+    let mut key_ids = Vec::new();
+    let mut session_keys = 0;
+    let mut position = 0;
+    while position < contents.len() {
+        let (tag, body_start, body_length) = read_packet_header(&contents[position..])?;
+        match tag {
+            PKESK_TAG => {
+                let body = contents.get(position + body_start..position + body_start + body_length?)?;
+                key_ids.push(read_pkesk_key_id(body)?);
+                session_keys += 1;
+            }
+            SKESK_TAG => session_keys += 1,
+            /* the session keys are followed by the encrypted data, which is the rest of the message */
+            SED_TAG | SEIPD_TAG | AEAD_TAG if session_keys > 0 => return Some(key_ids),
+            _ => return None,
+        }
+        position += body_start + body_length?;
+    }
+    None
+}
+
+/* Returns the number of recipient stanzas in the header of an age file, or None if it isn't one.
+ * age recipients can't be identified from the header */
+pub fn count_age_recipients(contents: &[u8]) -> Option<usize> {
+    if !contents.starts_with(AGE_HEADER) {
+        return None;
+    }
+
+    let mut stanzas = 0;
+    for line in contents[AGE_HEADER.len()..].split(|&byte| byte == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(AGE_MAC_PREFIX) {
+            return Some(stanzas);
+        }
+        if let Some(stanza) = line.strip_prefix(AGE_STANZA_PREFIX) {
+            let tag = stanza.split(' ').next().unwrap_or("");
+            if !tag.ends_with(AGE_GREASE_SUFFIX) {
+                stanzas += 1;
+            }
+        }
+    }
+    None
+}
+
+/* Returns the binary message of an ASCII armored one, without verifying its checksum */
+fn dearmor(contents: &[u8]) -> Option<Vec<u8>> {
+    let text = ::std::str::from_utf8(contents).ok()?;
+    let mut lines = text.lines().map(|line| line.trim_end());
+    if lines.next()? != ARMOR_HEADER {
+        return None;
+    }
+    /* armor headers, such as "Version: ...", end with a blank line */
+    for line in &mut lines {
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    let mut data = String::new();
+    for line in lines {
+        if line.starts_with(ARMOR_CHECKSUM_PREFIX) || line.starts_with(ARMOR_FOOTER_PREFIX) {
+            return decode_base64(&data);
+        }
+        data.push_str(line);
+    }
+    None
+}
+
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data.bytes().filter(|&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/* Returns (tag, header length, body length), the body length is None for indeterminate
+ * and partial lengths, which only data packets can have */
+fn read_packet_header(packet: &[u8]) -> Option<(u8, usize, Option<usize>)> {
+    let first = *packet.first()?;
+    if first & 0x80 == 0 {
+        return None;
+    }
+
+    if first & 0x40 != 0 {
+        let tag = first & 0x3f;
+        let length = *packet.get(1)? as usize;
+        return match length {
+            0..=191 => Some((tag, 2, Some(length))),
+            192..=223 => Some((tag, 3, Some(((length - 192) << 8) + *packet.get(2)? as usize + 192))),
+            255 => Some((tag, 6, Some(read_be(packet.get(2..6)?)))),
+            _ => Some((tag, 2, None)),
+        };
+    }
+
+    let tag = (first >> 2) & 0x0f;
+    match first & 0x03 {
+        0 => Some((tag, 2, Some(*packet.get(1)? as usize))),
+        1 => Some((tag, 3, Some(read_be(packet.get(1..3)?)))),
+        2 => Some((tag, 5, Some(read_be(packet.get(1..5)?)))),
+        _ => Some((tag, 1, None)),
+    }
+}
+
+fn read_pkesk_key_id(body: &[u8]) -> Option<String> {
+    match *body.first()? {
+        3 => Some(to_hex(body.get(1..9)?)),
+        /* version 6 packets hold the fingerprint, whose key id depends on the key version */
+        6 => {
+            let length = *body.get(1)? as usize;
+            if length == 0 {
+                return Some(to_hex(&[0; 8]));
+            }
+            let fingerprint = body.get(3..2 + length)?;
+            match *body.get(2)? {
+                4 if fingerprint.len() >= 8 => Some(to_hex(&fingerprint[fingerprint.len() - 8..])),
+                6 => Some(to_hex(fingerprint.get(..8)?)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn read_be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as usize)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use age;
+    use std::io::Write;
+    use super::*;
+
+    const KEY_ID: [u8; 8] = [0x7A, 0x00, 0x79, 0x79, 0x6A, 0x36, 0x0E, 0xDC];
+    /* a SEIPD packet with a new format header, its contents don't matter */
+    const SEIPD: &'static [u8] = &[0xD2, 0x03, 0x01, 0xAA, 0xBB];
+
+    fn pkesk_v3(key_id: &[u8]) -> Vec<u8> {
+        let mut body = vec![3];
+        body.extend_from_slice(key_id);
+        /* RSA, followed by a one byte MPI */
+        body.extend_from_slice(&[1, 0x00, 0x08, 0xFF]);
+        body
+    }
+
+    fn pkesk_v6(key_version: u8, fingerprint: &[u8]) -> Vec<u8> {
+        let mut body = vec![6, fingerprint.len() as u8 + 1, key_version];
+        body.extend_from_slice(fingerprint);
+        /* X25519, followed by an ephemeral key and the encrypted session key */
+        body.push(25);
+        body.extend_from_slice(&[0x42; 32]);
+        body.extend_from_slice(&[1, 0x42]);
+        body
+    }
+
+    fn new_format(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xC0 | tag];
+        if body.len() < 192 {
+            packet.push(body.len() as u8);
+        } else {
+            let length = body.len() - 192;
+            packet.extend_from_slice(&[(length >> 8) as u8 + 192, length as u8]);
+        }
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn message(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut message: Vec<u8> = packets.concat();
+        message.extend_from_slice(SEIPD);
+        message
+    }
+
+    #[test]
+    fn reads_new_format_headers() {
+        let contents = message(&[new_format(PKESK_TAG, &pkesk_v3(&KEY_ID)), new_format(PKESK_TAG, &pkesk_v3(&[0x11; 8]))]);
+        assert_eq!(read_pgp_recipients(&contents), Some(vec!["7A0079796A360EDC".to_string(), "1111111111111111".to_string()]));
+    }
+
+    #[test]
+    fn reads_two_byte_new_format_lengths() {
+        let mut body = pkesk_v3(&KEY_ID);
+        body.resize(300, 0);
+        let contents = message(&[new_format(PKESK_TAG, &body)]);
+        assert_eq!(read_pgp_recipients(&contents), Some(vec!["7A0079796A360EDC".to_string()]));
+    }
+
+    #[test]
+    fn reads_old_format_headers() {
+        let body = pkesk_v3(&KEY_ID);
+        let mut one_byte_length = vec![0x84, body.len() as u8];
+        one_byte_length.extend_from_slice(&body);
+        let mut two_byte_length = vec![0x85, 0x00, body.len() as u8];
+        two_byte_length.extend_from_slice(&body);
+        let mut four_byte_length = vec![0x86, 0x00, 0x00, 0x00, body.len() as u8];
+        four_byte_length.extend_from_slice(&body);
+        /* a SED packet with an indeterminate length */
+        let mut contents = [one_byte_length, two_byte_length, four_byte_length].concat();
+        contents.extend_from_slice(&[0xA7, 0xAA, 0xBB]);
+
+        assert_eq!(read_pgp_recipients(&contents), Some(vec!["7A0079796A360EDC".to_string(); 3]));
+    }
+
+    #[test]
+    fn reads_v6_pkesk() {
+        let v4_fingerprint: Vec<u8> = [[0x22; 12].to_vec(), KEY_ID.to_vec()].concat();
+        let v6_fingerprint: Vec<u8> = [KEY_ID.to_vec(), vec![0x33; 24]].concat();
+        let contents = message(&[new_format(PKESK_TAG, &pkesk_v6(4, &v4_fingerprint)), new_format(PKESK_TAG, &pkesk_v6(6, &v6_fingerprint))]);
+        assert_eq!(read_pgp_recipients(&contents), Some(vec!["7A0079796A360EDC".to_string(); 2]));
+    }
+
+    #[test]
+    fn reads_anonymous_recipients() {
+        let v3 = new_format(PKESK_TAG, &pkesk_v3(&[0; 8]));
+        let v6 = new_format(PKESK_TAG, &[6, 0, 25, 0x42, 1, 0x42]);
+        assert_eq!(read_pgp_recipients(&message(&[v3, v6])), Some(vec!["0000000000000000".to_string(); 2]));
+    }
+
+    #[test]
+    fn reads_passphrase_only_messages() {
+        let skesk = new_format(SKESK_TAG, &[4, 9, 3, 2, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x60]);
+        assert_eq!(read_pgp_recipients(&message(&[skesk])), Some(Vec::new()));
+    }
+
+    #[test]
+    fn reads_armored_messages() {
+        /* made with gpg --armor, with a comment header added */
+        let contents = "-----BEGIN PGP MESSAGE-----\n\
+            Comment: made by gpg\n\
+            \n\
+            hQGMA1959lZ1FGfEAQv8C2CuckJsa6vOLMGsEiy3I7B0PyAHm/3ZrkueAue2tOSM\n\
+            w9IabOBWyHntfuilC6iyjkl5SQCFyf+UcOHb2q9zxpY4V+qYsK4nMyfyxtFBqo33\n\
+            kq2gD4bAuc6N3SC8LMVwm2i6A2nUbNK+I6Gx3dD9WEu4AAcWuRQMBGEyzRDwlebF\n\
+            FpxidshH2lOQvTO9vTK14DUkuEWEw32hBc5rnw53DiEHlKE67Iv1aVg3no6jGoK1\n\
+            +QpohaiIfKPd+7eWuRR2SvVWcLWA7MmhIx6S9ACZPho4fdFI9xVimkjRQCP7WFeN\n\
+            0FoXDRdane81kae5gtDVWYjABRaWOxoi36Pw9wBk1n15y+4WR4FRQrQAL+5ndxxt\n\
+            qpq/g8WHguLH/N1US7JbNvLmpeudBerJ9c3avUdouVTpsqcNJL+1UunW8dakri1M\n\
+            V+c4kV3b6IEedMhGQCXZ0FEn2Rs3XGTysUmV+7IyQABaw+aGYw2ikHNrnsIOFT5s\n\
+            lOtL7oC1zwhKicDx8Wvi0jwBUfsCU7d1OeEydeiOSbCHF2jE2PtHrx5TwtILar0q\n\
+            XjZCOjqHudDoKfJhwV+5VEqGyEBCqWx/dVpjonc=\n\
+            =UgvD\n\
+            -----END PGP MESSAGE-----\n";
+        assert_eq!(read_pgp_recipients(contents.as_bytes()), Some(vec!["5F79F656751467C4".to_string()]));
+        /* a message without a checksum, truncated ones and ones that aren't base64 */
+        let contents = "-----BEGIN PGP MESSAGE-----\n\nwQ0DegB5eWo2DtwBAAj/0gMBqrs=\n-----END PGP MESSAGE-----\n";
+        assert_eq!(read_pgp_recipients(contents.as_bytes()), Some(vec!["7A0079796A360EDC".to_string()]));
+        assert_eq!(read_pgp_recipients(b"-----BEGIN PGP MESSAGE-----\n\nhQEMA3oAeXlqNg7cAQ==\n"), None);
+        assert_eq!(read_pgp_recipients(b"-----BEGIN PGP MESSAGE-----\n\nnot base64!\n-----END PGP MESSAGE-----\n"), None);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let contents = message(&[new_format(PKESK_TAG, &pkesk_v3(&KEY_ID))]);
+        /* cut in the encrypted data's header, in the PKESK body and in the PKESK header */
+        for length in &[contents.len() - SEIPD.len(), 8, 1] {
+            assert_eq!(read_pgp_recipients(&contents[..*length]), None);
+        }
+        assert_eq!(read_pgp_recipients(&[0xC1, 0xFF, 0x00]), None);
+        assert_eq!(read_pgp_recipients(&[0xC1, 0x05, 3, 0x11, 0x11, 0x11, 0x11]), None);
+        assert_eq!(read_pgp_recipients(&[]), None);
+    }
+
+    #[test]
+    fn rejects_non_openpgp_input() {
+        assert_eq!(read_pgp_recipients(b"password123\n"), None);
+        /* encrypted data without any session key, and a signature packet */
+        assert_eq!(read_pgp_recipients(SEIPD), None);
+        assert_eq!(read_pgp_recipients(&new_format(2, &[4, 0, 1, 8])), None);
+        /* an unknown PKESK version */
+        assert_eq!(read_pgp_recipients(&message(&[new_format(PKESK_TAG, &[5, 0, 0])])), None);
+        assert_eq!(read_pgp_recipients(b"age-encryption.org/v1\n"), None);
+    }
+
+    #[test]
+    fn counts_age_recipients() {
+        let identities = [age::x25519::Identity::generate(), age::x25519::Identity::generate()];
+        let recipients: Vec<age::x25519::Recipient> = identities.iter().map(|identity| identity.to_public()).collect();
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient)).unwrap();
+        let mut contents = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut contents).unwrap();
+        writer.write_all(b"secret").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(count_age_recipients(&contents), Some(2));
+        assert_eq!(count_age_recipients(b"age-encryption.org/v1\n-> X25519 abc\n"), None);
+        assert_eq!(count_age_recipients(&message(&[new_format(PKESK_TAG, &pkesk_v3(&KEY_ID))])), None);
+    }
+}
@@ -0,0 +1,112 @@
+use git2::Repository;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use crypto;
+use crypto::BackendKind;
+use error::GitCredError;
+use error::Result;
+use packets;
+
+/* Why a file can't be reencrypted */
+enum Blocker {
+    /* the caller can't decrypt the file */
+    Inaccessible(String),
+    /* the gpg ids of the folder of the file can't be resolved */
+    Unresolvable(String),
+}
+
+/* Collects the files that can't be reencrypted before anything gets changed, so that re-keying
+ * doesn't stop halfway through on the first file the caller can't decrypt */
+pub struct Preflight<'a> {
+    repo: &'a Repository,
+    secret_key_ids: HashMap<BackendKind, Option<Vec<String>>>,
+    blockers: Vec<(PathBuf, Blocker)>,
+}
+
+impl<'a> Preflight<'a> {
+    pub fn new(repo: &'a Repository) -> Preflight<'a> {
+        Preflight { repo, secret_key_ids: HashMap::new(), blockers: Vec::new() }
+    }
+
+    /* Checks that the file at `path` in the store, with the given contents, can be decrypted. Uses the
+     * key ids of the message when the backend knows its secret keys, and tries to decrypt it otherwise */
+    pub fn check_access(&mut self, path: &Path, full_path: &Path, contents: &[u8]) -> Result<bool> {
+        let backend_kind = crypto::get_backend_kind_for_ciphertext(self.repo, full_path)?;
+        let backend = backend_kind.create(self.repo)?;
+        if let Entry::Vacant(entry) = self.secret_key_ids.entry(backend_kind) {
+            entry.insert(backend.get_secret_key_ids()?);
+        }
+
+        let reason = match self.secret_key_ids[&backend_kind] {
+            Some(ref secret_key_ids) if backend_kind != BackendKind::Age => match packets::read_pgp_recipients(contents) {
+                None => Some("it isn't an encrypted file".to_string()),
+                /* anonymous recipients can only be found by trying */
+                Some(ref key_ids) if key_ids.iter().any(|key_id| key_id.chars().all(|c| c == '0')) => try_decrypt(&*backend, full_path),
+                Some(ref key_ids) if key_ids.iter().any(|key_id| secret_key_ids.contains(key_id)) => None,
+                Some(key_ids) => Some(format!("none of your secret keys can decrypt it, it's encrypted for {}", describe_key_ids(&key_ids))),
+            },
+            _ => try_decrypt(&*backend, full_path),
+        };
+        Ok(self.block(path, reason, Blocker::Inaccessible))
+    }
+
+    /* Records that the gpg ids of the folder of the file at `path` can't be resolved */
+    pub fn unresolvable(&mut self, path: &Path, reason: &str) {
+        self.block(path, Some(reason.to_string()), Blocker::Unresolvable);
+    }
+
+    /* Prints the blockers to stderr, and fails unless the other files can be reencrypted:
+     * there are no blockers, or only files the caller can't decrypt and they get skipped */
+    pub fn report(&self, skip_inaccessible: bool) -> Result<()> {
+        if self.blockers.is_empty() {
+            return Ok(());
+        }
+
+        let only_inaccessible = self.blockers.iter().all(|(_, blocker)| matches!(blocker, Blocker::Inaccessible(_)));
+        if skip_inaccessible && only_inaccessible {
+            eprintln!("Skipping {} file(s) you can't decrypt:", self.blockers.len());
+        } else {
+            eprintln!("Unable to reencrypt {} file(s):", self.blockers.len());
+        }
+        for &(ref path, ref blocker) in &self.blockers {
+            match *blocker {
+                Blocker::Inaccessible(ref reason) => eprintln!("  {}: {}", path.display(), reason),
+                Blocker::Unresolvable(ref reason) => eprintln!("  {}: gpg ids can't be resolved: {}", path.display(), reason),
+            }
+        }
+
+        if skip_inaccessible && only_inaccessible {
+            return Ok(());
+        }
+        if only_inaccessible {
+            eprintln!("Ask someone who can decrypt them to reencrypt them, or use --skip-inaccessible to leave them as they are");
+        }
+        Err(GitCredError::PreflightFailed(self.blockers.len()))
+    }
+
+    fn block<F: Fn(String) -> Blocker>(&mut self, path: &Path, reason: Option<String>, blocker: F) -> bool {
+        match reason {
+            Some(reason) => {
+                self.blockers.push((path.to_path_buf(), blocker(reason)));
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+fn try_decrypt(backend: &dyn crypto::CryptoBackend, path: &Path) -> Option<String> {
+    match backend.decrypt(path) {
+        Ok(_) => None,
+        Err(err) => Some(err.to_string().lines().next().unwrap_or("unable to decrypt it").to_string()),
+    }
+}
+
+fn describe_key_ids(key_ids: &[String]) -> String {
+    if key_ids.is_empty() {
+        return "a passphrase only".to_string();
+    }
+    key_ids.join(", ")
+}
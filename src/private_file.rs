@@ -0,0 +1,170 @@
+use std::env;
+use std::fs;
+use std::fs::DirBuilder;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use error::GitCredError;
+use error::Result;
+
+const PRIVATE_FILE_MODE: u32 = 0o600;
+const PRIVATE_DIR_MODE: u32 = 0o700;
+const SHM_DIR: &'static str = "/dev/shm";
+
+static PRIVATE_DIR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/* Creates (or truncates) a file only readable and writable by the current user */
+pub fn create(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_FILE_MODE)
+        .open(path)?;
+    /* the mode is only applied to new files */
+    file.set_permissions(fs::Permissions::from_mode(PRIVATE_FILE_MODE))?;
+    Ok(file)
+}
+
+pub fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = create(path)?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// A directory only accessible by the current user, preferably on a tmpfs (`/dev/shm`)
+/// so decrypted secrets never reach the disk.
+/// Its files are overwritten and the directory removed when dropped.
+pub struct PrivateDir {
+    path: PathBuf,
+}
+
+impl PrivateDir {
+    pub fn new() -> Result<PrivateDir> {
+        let shm = Path::new(SHM_DIR);
+        let parent = if shm.is_dir() && fs::metadata(shm)?.permissions().mode() & 0o002 != 0 {
+            shm.to_path_buf()
+        } else {
+            env::temp_dir()
+        };
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+        let count = PRIVATE_DIR_COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = parent.join(format!("git-cred-{}-{}-{}", process::id(), nanos, count));
+        DirBuilder::new().mode(PRIVATE_DIR_MODE).create(&path)?;
+        Ok(PrivateDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to a file only readable by the current user in the directory.
+    /// `name` must be a relative path that stays inside the directory.
+    pub fn write_file(&self, name: &str, contents: &[u8]) -> Result<PathBuf> {
+        let name = Path::new(name);
+        if name.as_os_str().is_empty() || !name.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            return Err(GitCredError::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid private file name: {}", name.display()))));
+        }
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            DirBuilder::new().recursive(true).mode(PRIVATE_DIR_MODE).create(parent)?;
+        }
+        write(&path, contents)?;
+        Ok(path)
+    }
+
+    /// Leaves the directory and its files in place instead of wiping them when dropped,
+    /// e.g. to not lose data that couldn't be saved elsewhere. Returns the path of the directory
+    pub fn keep(self) -> PathBuf {
+        let path = self.path.clone();
+        mem::forget(self);
+        path
+    }
+
+    /// Overwrites every file in the directory with zeros and removes the directory
+    pub fn wipe(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        overwrite_files(&self.path)?;
+        fs::remove_dir_all(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        if let Err(err) = self.wipe() {
+            eprintln!("Unable to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+fn overwrite_files(path: &Path) -> Result<()> {
+    for file in fs::read_dir(path)? {
+        let file_res = file?;
+        if file_res.file_type()?.is_dir() {
+            overwrite_files(&file_res.path())?;
+        } else if file_res.file_type()?.is_file() {
+            let len = file_res.metadata()?.len() as usize;
+            let mut file = OpenOptions::new().write(true).open(file_res.path())?;
+            file.write_all(&vec![0; len])?;
+            file.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_private_files() {
+        let dir = PrivateDir::new().unwrap();
+        assert_eq!(fs::metadata(dir.path()).unwrap().permissions().mode() & 0o777, PRIVATE_DIR_MODE);
+
+        let path = dir.write_file("versions/./ours", b"secret").unwrap();
+        assert_eq!(path, dir.path().join("versions/ours"));
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, PRIVATE_FILE_MODE);
+        assert_eq!(fs::metadata(dir.path().join("versions")).unwrap().permissions().mode() & 0o777, PRIVATE_DIR_MODE);
+    }
+
+    #[test]
+    fn rejects_names_leaving_the_directory() {
+        let dir = PrivateDir::new().unwrap();
+        for name in &["", "..", "../escaped", "versions/../../escaped", "/tmp/escaped"] {
+            assert!(dir.write_file(name, b"secret").is_err(), "{}", name);
+        }
+        assert!(!dir.path().parent().unwrap().join("escaped").exists());
+    }
+
+    #[test]
+    fn wipes_the_directory() {
+        let dir = PrivateDir::new().unwrap();
+        dir.write_file("a/b", b"secret").unwrap();
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+
+        let dir = PrivateDir::new().unwrap();
+        let path = dir.keep();
+        assert!(path.is_dir());
+        fs::remove_dir(path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use git2::Repository;
+use std::collections::HashMap;
+use std::path::Path;
+use audit::Problem;
+use crypto::BackendKind;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use packets;
+use resolver;
+
+/* Compares who files are encrypted for with the recipients of their folder, without decrypting.
+ * Resolves each gpg id once, most files share the recipients of their folder */
+pub struct RecipientChecker<'a> {
+    repo: &'a Repository,
+    /* the resolved key ids, or why the gpg id can't be resolved */
+    resolved: HashMap<(BackendKind, String), ::std::result::Result<Vec<String>, String>>,
+}
+
+impl<'a> RecipientChecker<'a> {
+    pub fn new(repo: &'a Repository) -> RecipientChecker<'a> {
+        RecipientChecker { repo, resolved: HashMap::new() }
+    }
+
+    pub fn check(&mut self, path: &Path, contents: &[u8]) -> Result<Option<Problem>> {
+        let repo = self.repo;
+        let backend_kind = encrypt::get_backend_kind_for_file(repo, path)?;
+        let gpg_ids = encrypt::get_gpg_ids_for_file(repo, path)?;
+        self.check_for(path, backend_kind, &gpg_ids, contents)
+    }
+
+    /* Like check, with the backend and gpg ids of the folder of the file given by the caller.
+     * Gpg ids that can't be resolved are a problem of the file rather than an error, so that
+     * the other files still get checked */
+    pub fn check_for(&mut self, path: &Path, backend_kind: BackendKind, gpg_ids: &[String], contents: &[u8]) -> Result<Option<Problem>> {
+        if gpg_ids.is_empty() {
+            return Ok(Some(Problem::Unresolvable(GitCredError::NoRecipients(path.to_path_buf()).to_string())));
+        }
+
+        let mut expected = Vec::new();
+        for gpg_id in gpg_ids {
+            match self.resolve(backend_kind, gpg_id) {
+                Ok(key_ids) => expected.push((gpg_id.clone(), key_ids)),
+                Err(reason) => return Ok(Some(Problem::Unresolvable(reason))),
+            }
+        }
+        Ok(compare(backend_kind, contents, &expected))
+    }
+
+    /* Records how a gpg id resolves, for callers that already previewed it */
+    pub fn add_resolution(&mut self, backend_kind: BackendKind, gpg_id: &str, resolved: ::std::result::Result<Vec<String>, String>) {
+        self.resolved.insert((backend_kind, gpg_id.to_string()), resolved);
+    }
+
+    /* Returns the key ids (or age recipients) a gpg id stands for. Only previews the resolution,
+     * so that checking doesn't import keys or write lock files */
+    fn resolve(&mut self, backend_kind: BackendKind, gpg_id: &str) -> ::std::result::Result<Vec<String>, String> {
+        let cache_key = (backend_kind, gpg_id.to_string());
+        if let Some(resolved) = self.resolved.get(&cache_key) {
+            return resolved.clone();
+        }
+
+        let repo = self.repo;
+        let resolved = if backend_kind == BackendKind::Age {
+            resolver::preview_age_name(repo, gpg_id).map(|(recipients, _)| recipients)
+        } else {
+            backend_kind.create(repo).and_then(|backend| resolver::preview_name(repo, &*backend, gpg_id)).map(|preview| preview.key_ids)
+        };
+        let resolved = resolved.map_err(|err| err.to_string());
+        self.resolved.insert(cache_key, resolved.clone());
+        resolved
+    }
+}
+
+/* Compares who the contents are encrypted for with the key ids (or age recipients) of each gpg id */
+pub fn compare(backend_kind: BackendKind, contents: &[u8], expected: &[(String, Vec<String>)]) -> Option<Problem> {
+    if backend_kind == BackendKind::Age {
+        let found = match packets::count_age_recipients(contents) {
+            Some(found) => found,
+            None => return Some(Problem::NotEncrypted),
+        };
+        let expected = expected.iter().map(|(_, recipients)| recipients.len()).sum();
+        if found != expected {
+            return Some(Problem::WrongRecipientCount { expected, found });
+        }
+        return None;
+    }
+
+    let found = match packets::read_pgp_recipients(contents) {
+        Some(key_ids) => key_ids,
+        None => return Some(Problem::NotEncrypted),
+    };
+    let missing: Vec<String> = expected.iter()
+        .filter(|(_, key_ids)| !key_ids.iter().any(|key_id| found.contains(key_id)))
+        .map(|(gpg_id, _)| gpg_id.clone())
+        .collect();
+    let extra: Vec<String> = found.into_iter()
+        .filter(|key_id| !expected.iter().any(|(_, key_ids)| key_ids.contains(key_id)))
+        .collect();
+
+    if missing.is_empty() && extra.is_empty() {
+        return None;
+    }
+    Some(Problem::WrongRecipients { missing, extra })
+}
@@ -0,0 +1,466 @@
+use git2::Repository;
+use signal_hook::consts::signal::SIGHUP;
+use signal_hook::consts::signal::SIGINT;
+use signal_hook::consts::signal::SIGTERM;
+use libc;
+use libc::c_int;
+use signal_hook::low_level;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Once;
+use std::thread;
+use audit::Problem;
+use crypto;
+use crypto::BackendKind;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use preflight::Preflight;
+use recipients::RecipientChecker;
+
+const STAGING_FOLDER_NAME: &'static str = "cred-reencrypt";
+
+/// How the secrets of a folder get reencrypted
+#[derive(Clone, Debug, Default)]
+pub struct ReencryptOptions {
+    /// Reencrypt every file, even the ones already encrypted for the recipients of their folder
+    pub force: bool,
+    /// Number of files reencrypted at the same time, 0 for one per CPU
+    pub jobs: usize,
+    /// Leave the files the caller can't decrypt as they are instead of failing
+    pub skip_inaccessible: bool,
+    /// Only print what would change, without touching the store or the keyring
+    pub dry_run: bool,
+}
+
+/* A file to reencrypt, with the backend and resolved recipients of its folder */
+struct Job {
+    path: PathBuf,
+    backend_kind: BackendKind,
+    recipients: Vec<String>,
+}
+
+/* Sets the gpg ids (and backend) of the folder and reencrypts it. If anything fails or the user
+ * interrupts, the .gpg_id and .backend files of the folder are restored along with every secret */
+pub fn init_folder(repo: &Repository, folder: &Path, recipients: Vec<String>, backend: Option<&str>, options: &ReencryptOptions) -> Result<()> {
+    rekey_folder(repo, folder, options, || {
+        if let Some(backend) = backend {
+            git_utils::write_backend_file(repo, folder, backend)?;
+        }
+        git_utils::create_sub_dir(repo, folder, recipients)
+    })
+}
+
+/* Replaces the .gpg_id file of the folder with the given contents and reencrypts the folder, the same way as init_folder */
+pub fn write_gpg_id_file(repo: &Repository, folder: &Path, contents: &str, options: &ReencryptOptions) -> Result<()> {
+    rekey_folder(repo, folder, options, || {
+        let folder_path = git_utils::get_credentials_dir(repo)?.join(folder);
+        fs::create_dir_all(&folder_path)?;
+        fs::write(folder_path.join(".gpg_id"), contents)?;
+        Ok(())
+    })
+}
+
+/* Changes the .gpg_id or .backend files of the folder and reencrypts it, restoring them if anything fails */
+fn rekey_folder<F: FnOnce() -> Result<()>>(repo: &Repository, folder: &Path, options: &ReencryptOptions, change: F) -> Result<()> {
+    let interrupt = InterruptGuard::new()?;
+    let folder_path = git_utils::get_credentials_dir(repo)?.join(folder);
+    let snapshot = Snapshot::take(&folder_path)?;
+
+    let mut result = change();
+    if result.is_ok() {
+        result = list_files(repo, &[folder.to_path_buf()]).and_then(|files| reencrypt(repo, files, options, &interrupt));
+    }
+
+    if result.is_err() {
+        snapshot.restore()?;
+    }
+    result
+}
+
+/* Reencrypts the given files, and the files under the given folders, whose recipients don't match the
+ * recipients of their folder. Either every file is reencrypted or, if anything fails or the user
+ * interrupts, none is */
+pub fn reencrypt_paths(repo: &Repository, paths: &[PathBuf], options: &ReencryptOptions) -> Result<()> {
+    let files = list_files(repo, paths)?;
+    let interrupt = InterruptGuard::new()?;
+    reencrypt(repo, files, options, &interrupt)
+}
+
+/* Returns the secrets at the given paths of the store, and under the given folders, without duplicates */
+pub fn list_files(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut files = Vec::new();
+    for path in paths {
+        let full_path = credentials_dir.join(path);
+        if full_path.is_dir() {
+            files.append(&mut encrypt::list_folder(repo, &full_path)?);
+        } else if full_path.is_file() {
+            files.push(path.clone());
+        } else {
+            return Err(GitCredError::Io(io::Error::new(io::ErrorKind::NotFound, format!("No such file or folder in credential store: {}", path.display()))));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Which files reencrypting comes down to, decided without changing anything
+pub struct Plan<'a> {
+    /// The files to reencrypt
+    pub files: Vec<PathBuf>,
+    /// How many files are already encrypted for the recipients of their folder and left as is
+    pub skipped: usize,
+    /// The files that can't be reencrypted, to report before anything changes
+    pub preflight: Preflight<'a>,
+}
+
+/* Decides which files get reencrypted: the ones the caller can decrypt that aren't encrypted for the
+ * recipients of their folder yet, or all of them with --force. `folder_target` gives the backend and
+ * gpg ids of the folder of a file, as the store is or as init would leave it. Gpg ids are only
+ * previewed by the checker, so planning doesn't import keys or write lock files */
+pub fn plan<'a, F>(repo: &'a Repository, files: Vec<PathBuf>, options: &ReencryptOptions, checker: &mut RecipientChecker, interrupt: Option<&InterruptGuard>, mut folder_target: F) -> Result<Plan<'a>>
+    where F: FnMut(&Path) -> Result<(BackendKind, Vec<String>)>
+{
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let mut preflight = Preflight::new(repo);
+    let mut planned = Vec::new();
+    let mut skipped = 0;
+    for file in files {
+        if let Some(interrupt) = interrupt {
+            interrupt.check()?;
+        }
+        let full_path = credentials_dir.join(&file);
+        let contents = fs::read(&full_path)?;
+        let problem = match folder_target(&file) {
+            Ok((backend_kind, gpg_ids)) => {
+                let problem = checker.check_for(&file, backend_kind, &gpg_ids, &contents)?;
+                /* the recipients of age files can't be identified, so they're always reencrypted */
+                if !options.force && problem.is_none() && backend_kind != BackendKind::Age {
+                    skipped += 1;
+                    continue;
+                }
+                problem
+            }
+            Err(err) => Some(Problem::Unresolvable(err.to_string())),
+        };
+
+        match problem {
+            Some(Problem::Unresolvable(reason)) => preflight.unresolvable(&file, &reason),
+            _ if preflight.check_access(&file, &full_path, &contents)? => planned.push(file),
+            _ => {}
+        }
+    }
+    Ok(Plan { files: planned, skipped, preflight })
+}
+
+fn reencrypt(repo: &Repository, files: Vec<PathBuf>, options: &ReencryptOptions, interrupt: &InterruptGuard) -> Result<()> {
+    let credentials_dir = git_utils::get_credentials_dir(repo)?;
+    let mut checker = RecipientChecker::new(repo);
+    let plan = plan(repo, files, options, &mut checker, Some(interrupt), |file| {
+        Ok((encrypt::get_backend_kind_for_file(repo, file)?, encrypt::get_gpg_ids_for_file(repo, file)?))
+    })?;
+    plan.preflight.report(options.skip_inaccessible)?;
+
+    /* resolving recipients may import keys and write lock files, so it's only done once nothing
+     * stands in the way, once per folder */
+    let mut targets: HashMap<PathBuf, (BackendKind, Vec<String>)> = HashMap::new();
+    let mut jobs = Vec::new();
+    for file in plan.files {
+        interrupt.check()?;
+        let folder = file.parent().map(|parent| parent.to_path_buf()).unwrap_or(PathBuf::new());
+        if !targets.contains_key(&folder) {
+            targets.insert(folder.clone(), encrypt::get_encryption_target(repo, &file)?);
+        }
+        let (backend_kind, ref recipients) = targets[&folder];
+        jobs.push(Job { path: file, backend_kind, recipients: recipients.clone() });
+    }
+
+    /* new ciphertexts are written to a staging folder in the git directory, so that they never get
+     * committed, and only replace the current ones once they were all written */
+    let staging_dir = repo.path().join(STAGING_FOLDER_NAME).join(process::id().to_string());
+    let new_dir = staging_dir.join("new");
+    let backup_dir = staging_dir.join("old");
+    let mut result = run_jobs(repo, &new_dir, &jobs, options.jobs, interrupt);
+    /* gpg gets the interrupt too, report it rather than gpg failing */
+    if result.is_err() && interrupt.check().is_err() {
+        result = interrupt.check();
+    }
+    let mut restored = true;
+    if result.is_ok() {
+        let (swapped, swap_result) = swap(&credentials_dir, &new_dir, &backup_dir, &jobs, interrupt);
+        if swap_result.is_err() {
+            restored = rollback(&credentials_dir, &backup_dir, &jobs[..swapped]);
+        }
+        result = swap_result;
+    }
+    /* the backups of the files that couldn't be put back are the only copies left of their ciphertexts */
+    if !restored {
+        eprintln!("The previous versions of the files that couldn't be restored are kept in {}", backup_dir.display());
+    } else if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    result?;
+
+    if plan.skipped > 0 {
+        println!("{} file(s) already encrypted for their recipients, use --force to reencrypt them anyway", plan.skipped);
+    }
+    Ok(())
+}
+
+/* Runs the jobs on a pool of worker threads, printing them in order as they complete.
+ * Stops handing out jobs after the first failure, and returns the failure of the first job that failed */
+fn run_jobs(repo: &Repository, new_dir: &Path, jobs: &[Job], worker_count: usize, interrupt: &InterruptGuard) -> Result<()> {
+    let worker_count = match worker_count {
+        0 => thread::available_parallelism().map(|count| count.get()).unwrap_or(1),
+        count => count,
+    };
+    let worker_count = cmp::max(cmp::min(worker_count, jobs.len()), 1);
+
+    let repo_path = repo.path().to_path_buf();
+    let credentials_dir = git_utils::get_credentials_dir(repo)?;
+    let next_job = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..worker_count {
+            let sender = sender.clone();
+            let (repo_path, credentials_dir, next_job, failed) = (&repo_path, &credentials_dir, &next_job, &failed);
+            scope.spawn(move || {
+                /* repositories can't be shared between threads */
+                let worker_repo = Repository::open(repo_path);
+                while !failed.load(Ordering::SeqCst) {
+                    let index = next_job.fetch_add(1, Ordering::SeqCst);
+                    if index >= jobs.len() {
+                        break;
+                    }
+                    let result = match worker_repo {
+                        Ok(ref worker_repo) => interrupt.check().and_then(|_| run_job(worker_repo, credentials_dir, new_dir, &jobs[index])),
+                        Err(ref err) => Err(git2::Error::from_str(err.message()).into()),
+                    };
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    let _ = sender.send((index, result));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut results: Vec<Option<Result<()>>> = jobs.iter().map(|_| None).collect();
+        let mut printed = 0;
+        for (index, result) in receiver {
+            results[index] = Some(result);
+            while printed < jobs.len() {
+                match results[printed] {
+                    Some(Ok(())) => println!("reencrypting file: {}", jobs[printed].path.display()),
+                    Some(Err(_)) => return results[printed].take().unwrap(),
+                    None => break,
+                }
+                printed += 1;
+            }
+        }
+
+        /* the earlier jobs all succeeded, so this is the first failure */
+        match results.iter_mut().find(|result| matches!(result, Some(Err(_)))) {
+            Some(result) => result.take().unwrap(),
+            None => Ok(()),
+        }
+    })
+}
+
+fn run_job(repo: &Repository, credentials_dir: &Path, new_dir: &Path, job: &Job) -> Result<()> {
+    let path = credentials_dir.join(&job.path);
+    let contents = crypto::get_backend_for_ciphertext(repo, &path)?.decrypt(&path)?;
+
+    let new_path = new_dir.join(&job.path);
+    fs::create_dir_all(new_path.parent().unwrap())?;
+    let recipients: Vec<&str> = job.recipients.iter().map(|recipient| recipient.as_str()).collect();
+    job.backend_kind.create(repo)?.encrypt(&new_path, &contents, &recipients)
+}
+
+/* Replaces the current ciphertexts with the new ones, keeping a link to each current one in the
+ * backup folder so that they can all be put back if anything fails or the user interrupts.
+ * Returns how many files were replaced, and stops at the first failure */
+fn swap(credentials_dir: &Path, new_dir: &Path, backup_dir: &Path, jobs: &[Job], interrupt: &InterruptGuard) -> (usize, Result<()>) {
+    for (swapped, job) in jobs.iter().enumerate() {
+        let result = interrupt.check().and_then(|_| swap_file(credentials_dir, new_dir, backup_dir, &job.path));
+        if result.is_err() {
+            return (swapped, result);
+        }
+    }
+    (jobs.len(), Ok(()))
+}
+
+/* Puts back the ciphertexts of the swapped jobs from the backup folder, going on past failures.
+ * Returns whether they were all put back */
+fn rollback(credentials_dir: &Path, backup_dir: &Path, swapped: &[Job]) -> bool {
+    let mut restored = true;
+    for job in swapped {
+        if let Err(err) = move_file(&backup_dir.join(&job.path), &credentials_dir.join(&job.path)) {
+            eprintln!("Unable to restore {}: {}", job.path.display(), err);
+            restored = false;
+        }
+    }
+    restored
+}
+
+fn swap_file(credentials_dir: &Path, new_dir: &Path, backup_dir: &Path, path: &Path) -> Result<()> {
+    let backup_path = backup_dir.join(path);
+    fs::create_dir_all(backup_path.parent().unwrap())?;
+    /* the store can be on another filesystem than the git directory (see creds.location) */
+    if let Err(err) = fs::hard_link(credentials_dir.join(path), &backup_path) {
+        if !is_cross_device(&err) {
+            return Err(err.into());
+        }
+        fs::copy(credentials_dir.join(path), &backup_path)?;
+    }
+    move_file(&new_dir.join(path), &credentials_dir.join(path))?;
+    Ok(())
+}
+
+/* Renames `from` over `to`. Across filesystems, `from` is first copied next to `to` so that `to`
+ * is still replaced by a rename, which is atomic: it's never left truncated */
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    let err = match fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+    if !is_cross_device(&err) {
+        return Err(err);
+    }
+
+    let name = to.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let staged = to.with_file_name(format!(".{}.cred-reencrypt-{}", name, process::id()));
+    let result = fs::copy(from, &staged).and_then(|_| fs::rename(&staged, to));
+    if result.is_err() && staged.exists() {
+        let _ = fs::remove_file(&staged);
+    }
+    result?;
+    fs::remove_file(from)
+}
+
+fn is_cross_device(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EXDEV)
+}
+
+/* The .gpg_id and .backend files of a folder, to put them back if init fails */
+struct Snapshot {
+    folder: PathBuf,
+    /* the folder, or its first parent, that didn't exist and gets created by init */
+    created_folder: Option<PathBuf>,
+    files: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl Snapshot {
+    fn take(folder: &Path) -> Result<Snapshot> {
+        let mut created_folder = None;
+        let mut ancestor = folder;
+        while !ancestor.exists() {
+            created_folder = Some(ancestor.to_path_buf());
+            ancestor = match ancestor.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+
+        let mut files = Vec::new();
+        for name in &[".gpg_id", ".backend"] {
+            let path = folder.join(name);
+            let contents = if path.is_file() { Some(fs::read(&path)?) } else { None };
+            files.push((path, contents));
+        }
+        Ok(Snapshot { folder: folder.to_path_buf(), created_folder, files })
+    }
+
+    fn restore(&self) -> Result<()> {
+        if let Some(ref created_folder) = self.created_folder {
+            if created_folder.exists() {
+                fs::remove_dir_all(created_folder)?;
+            }
+            return Ok(());
+        }
+
+        for (path, contents) in &self.files {
+            match *contents {
+                Some(ref contents) => fs::write(path, contents)?,
+                None if path.exists() => fs::remove_file(path)?,
+                None => {}
+            }
+        }
+        eprintln!("Restored the gpg ids of {}", self.folder.display());
+        Ok(())
+    }
+}
+
+/* signal-hook can't uninstall its handlers, so they're installed once and stay. While no guard is
+ * alive they leave the signals to the disposition the process had before */
+static INSTALL_HANDLERS: Once = Once::new();
+static ACTIVE_GUARDS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/* While alive, SIGINT, SIGTERM and SIGHUP don't kill the process but are recorded,
+ * so that the store can be restored before exiting */
+pub struct InterruptGuard;
+
+impl InterruptGuard {
+    fn new() -> Result<InterruptGuard> {
+        let mut result = Ok(());
+        INSTALL_HANDLERS.call_once(|| result = install_handlers());
+        result?;
+
+        if ACTIVE_GUARDS.fetch_add(1, Ordering::SeqCst) == 0 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+        }
+        Ok(InterruptGuard)
+    }
+
+    fn check(&self) -> Result<()> {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(GitCredError::Io(io::Error::new(io::ErrorKind::Interrupted, "Interrupted, the store was left unchanged")));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        ACTIVE_GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn install_handlers() -> Result<()> {
+    for &signal in &[SIGINT, SIGTERM, SIGHUP] {
+        /* handlers the process had before are still called by signal-hook, but a default disposition
+         * has to be emulated, it would otherwise be lost */
+        let is_default = has_default_disposition(signal);
+        let action = move || {
+            if ACTIVE_GUARDS.load(Ordering::SeqCst) > 0 {
+                INTERRUPTED.store(true, Ordering::SeqCst);
+            } else if is_default {
+                let _ = low_level::emulate_default_handler(signal);
+            }
+        };
+        unsafe { low_level::register(signal, action)? };
+    }
+    Ok(())
+}
+
+fn has_default_disposition(signal: c_int) -> bool {
+    let mut current: libc::sigaction = unsafe { mem::zeroed() };
+    let result = unsafe { libc::sigaction(signal, ptr::null(), &mut current) };
+    result == 0 && current.sa_sigaction == libc::SIG_DFL
+}
@@ -0,0 +1,145 @@
+use git2::Repository;
+use std::path::PathBuf;
+use decrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use transfer;
+
+const PLACEHOLDER_START: &'static str = "{{";
+const PLACEHOLDER_END: &'static str = "}}";
+const PLACEHOLDER_FUNCTION: &'static str = "cred";
+
+/* A `{{ cred "path" }}` placeholder, start and end are byte offsets into the template */
+struct Placeholder {
+    start: usize,
+    end: usize,
+    line: usize,
+    path: PathBuf,
+}
+
+/* Replaces every placeholder with the decrypted secret it references */
+pub fn render(repo: &Repository, template: &str) -> Result<Vec<u8>> {
+    let placeholders = parse(template)?;
+    check_exists(repo, &placeholders)?;
+
+    let mut rendered = Vec::new();
+    let mut position = 0;
+    for placeholder in &placeholders {
+        rendered.extend_from_slice(&template.as_bytes()[position..placeholder.start]);
+        rendered.append(&mut decrypt::decrypt(repo, &placeholder.path)?);
+        position = placeholder.end;
+    }
+    rendered.extend_from_slice(&template.as_bytes()[position..]);
+    Ok(rendered)
+}
+
+/* Checks that every placeholder is well formed and references a secret in the store, without decrypting */
+pub fn check(repo: &Repository, template: &str) -> Result<()> {
+    check_exists(repo, &parse(template)?)
+}
+
+/* Reports every missing secret at once rather than just the first */
+fn check_exists(repo: &Repository, placeholders: &[Placeholder]) -> Result<()> {
+    let credentials_dir = git_utils::get_credentials_location(repo)?;
+    let missing: Vec<String> = placeholders.iter()
+        .filter(|placeholder| !credentials_dir.join(&placeholder.path).is_file())
+        .map(|placeholder| format!("line {}: no such secret in credential store: {}", placeholder.line, placeholder.path.display()))
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(GitCredError::Template(missing.join("\n")))
+    }
+}
+
+/* Only `{{` followed by `cred` is ours, anything else is left alone so templates can
+ * also be processed by other tools */
+fn parse(template: &str) -> Result<Vec<Placeholder>> {
+    let mut placeholders = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = template[position..].find(PLACEHOLDER_START) {
+        let start = position + offset;
+        let inner_start = start + PLACEHOLDER_START.len();
+        let line = template[..start].matches('\n').count() + 1;
+        position = inner_start;
+
+        let expression = template[inner_start..].trim_start();
+        if !expression.starts_with(PLACEHOLDER_FUNCTION)
+            || !expression[PLACEHOLDER_FUNCTION.len()..].starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        let inner_end = match template[inner_start..].find(PLACEHOLDER_END) {
+            Some(offset) => inner_start + offset,
+            None => return Err(template_error(line, "unterminated placeholder, expected '}}'")),
+        };
+        let argument = template[inner_start..inner_end].trim()[PLACEHOLDER_FUNCTION.len()..].trim();
+        if argument.len() < 2 || !argument.starts_with('"') || !argument.ends_with('"') {
+            return Err(template_error(line, &format!("expected {{{{ cred \"path\" }}}}, found {{{{{}}}}}", &template[inner_start..inner_end])));
+        }
+
+        let raw_path = &argument[1..argument.len() - 1];
+        let path = transfer::check_store_path(raw_path.as_ref(), false)
+            .map_err(|_| template_error(line, &format!("invalid path in credential store: {}", raw_path)))?;
+        let end = inner_end + PLACEHOLDER_END.len();
+        placeholders.push(Placeholder { start, end, line, path });
+        position = end;
+    }
+    Ok(placeholders)
+}
+
+fn template_error(line: usize, message: &str) -> GitCredError {
+    GitCredError::Template(format!("line {}: {}", line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    fn error_message(template: &str) -> String {
+        match parse(template) {
+            Err(GitCredError::Template(message)) => message,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("{} parsed", template),
+        }
+    }
+
+    #[test]
+    fn finds_placeholders() {
+        let template = "user: {{cred \"db/user\"}}\npassword: {{  cred   \"db/password\"  }}\n";
+        let placeholders = parse(template).unwrap();
+        assert_eq!(placeholders.len(), 2);
+
+        assert_eq!(&template[placeholders[0].start..placeholders[0].end], "{{cred \"db/user\"}}");
+        assert_eq!(placeholders[0].path, Path::new("db/user"));
+        assert_eq!(placeholders[0].line, 1);
+
+        assert_eq!(&template[placeholders[1].start..placeholders[1].end], "{{  cred   \"db/password\"  }}");
+        assert_eq!(placeholders[1].path, Path::new("db/password"));
+        assert_eq!(placeholders[1].line, 2);
+    }
+
+    #[test]
+    fn leaves_other_placeholders_alone() {
+        let placeholders = parse("{{ .Values.name }} {{credentials}} {{ credit \"x\" }} {{").unwrap();
+        assert!(placeholders.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        assert_eq!(error_message("a\n{{ cred \"db/user\""), "line 2: unterminated placeholder, expected '}}'");
+        assert_eq!(error_message("{{ cred db/user }}"), "line 1: expected {{ cred \"path\" }}, found {{ cred db/user }}");
+        assert_eq!(error_message("{{ cred \" }}"), "line 1: expected {{ cred \"path\" }}, found {{ cred \" }}");
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_store() {
+        assert_eq!(error_message("{{ cred \"../secret\" }}"), "line 1: invalid path in credential store: ../secret");
+        assert_eq!(error_message("{{ cred \"/etc/passwd\" }}"), "line 1: invalid path in credential store: /etc/passwd");
+        assert_eq!(error_message("{{ cred \".gpg_id\" }}"), "line 1: invalid path in credential store: .gpg_id");
+        assert_eq!(error_message("{{ cred \"\" }}"), "line 1: invalid path in credential store: ");
+    }
+}
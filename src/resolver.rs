@@ -1,109 +1,194 @@
 use git_utils;
-use gpg;
+use age_backend;
+use crypto;
+use crypto::CryptoBackend;
 use git2::Repository;
 use std::path::PathBuf;
 use std::fs;
-use util::error_out;
+use error::GitCredError;
+use error::Result;
 use github;
 
 const KEY_FOLDER_NAME: &'static str = ".keys";
 const LOCK_FOLDER_NAME: &'static str = "locks";
 const PUBLIC_KEY_FOLDER_NAME: &'static str = "keys";
-
-pub fn resolve_name(repo: &Repository, uid: &str) -> String {
-    let key_id = get_locked_key_id(repo, uid);
-    if key_id.is_none() {
-        if gpg::has_key(uid) {
-            let new_key_id = gpg::get_key_id(uid);
-            write_lock_file(repo, uid, &new_key_id);
-            return new_key_id;
-        } else {
-            let ghub_key = github::get_key(uid, None);
-            if ghub_key.is_some() {
-                let ghub_key_str = ghub_key.unwrap();
-                let new_key_id = gpg::import_key(&ghub_key_str);
-                write_lock_file(repo, uid, &new_key_id);
-                println!("Found key for user: {} in github", uid);
-                return new_key_id;
-            } 
-            error_out(&format!("Unable to find key for user: {}", uid));
+const AGE_LOCK_FOLDER_NAME: &'static str = "age-locks";
+
+/* Resolves a uid to the key id of its key, importing the key and locking the uid to it as needed */
+pub fn resolve_name(repo: &Repository, backend: &dyn CryptoBackend, uid: &str) -> Result<String> {
+    let decision = decide_name(repo, backend, uid)?;
+    let key_id = match decision.location {
+        KeyLocation::Keyring(key_id) => key_id,
+        KeyLocation::Import(source, key) => {
+            let key_id = backend.import_key(&key)?;
+            match source {
+                KeySource::Github => eprintln!("Found key for user: {} in github", uid),
+                _ => eprintln!("Found key for user: {} in repo", uid),
+            }
+            key_id
         }
+    };
+    if decision.locked_key_id.is_none() {
+        write_lock_file(repo, uid, &key_id)?;
     }
+    Ok(key_id)
+}
 
-    let key_id_str = key_id.unwrap();
-    if gpg::has_key(&key_id_str) {
-        return key_id_str;
-    }
+/* Where resolve_name finds the key of a uid */
+pub enum KeySource {
+    Keyring,
+    /* the key saved in the store with save-key, imported into the keyring */
+    Store,
+    /* the key published on github, imported into the keyring */
+    Github,
+}
 
-    if get_saved_key(repo, &key_id_str).is_some() {
-        println!("Found key for user: {} in repo", uid);
-        return key_id_str;
-    }
+/* What resolve_name would do for a uid */
+pub struct KeyPreview {
+    /* the key id resolve_name returns, i.e. the fingerprint of the key */
+    pub fingerprint: String,
+    pub key_ids: Vec<String>,
+    pub source: KeySource,
+    /* the lock file resolve_name would create */
+    pub new_lock_file: Option<PathBuf>,
+}
 
-    let ghub_key = github::get_key(uid, Some(key_id_str.clone()));
-    if ghub_key.is_some() {
-        println!("Found key for user {} in github", uid);
-        return gpg::import_key(&ghub_key.unwrap());
+/* Works out how resolve_name would resolve the uid, without importing keys or writing lock files */
+pub fn preview_name(repo: &Repository, backend: &dyn CryptoBackend, uid: &str) -> Result<KeyPreview> {
+    let decision = decide_name(repo, backend, uid)?;
+    let new_lock_file = match decision.locked_key_id {
+        Some(_) => None,
+        None => Some(get_key_path(repo, LOCK_FOLDER_NAME, uid)?),
+    };
+    match decision.location {
+        KeyLocation::Keyring(key_id) => {
+            let key_ids = backend.get_key_ids(&key_id)?;
+            Ok(KeyPreview { fingerprint: key_id, key_ids, source: KeySource::Keyring, new_lock_file })
+        }
+        KeyLocation::Import(source, key) => {
+            let (fingerprint, key_ids) = backend.inspect_key(&key)?;
+            Ok(KeyPreview { fingerprint, key_ids, source, new_lock_file })
+        }
     }
+}
 
-    error_out(&format!("Unknown key id: {}, for user: {}", key_id_str, uid))
+/* Where the key of a uid is */
+enum KeyLocation {
+    /* in the keyring, with its key id */
+    Keyring(String),
+    /* the key to import into the keyring */
+    Import(KeySource, String),
 }
 
-pub fn set_key(repo: &Repository, uid: &str, pub_key: &str) {
-    save_key(repo, pub_key, uid);
+/* How to resolve a uid, shared by resolve_name and preview_name */
+struct Decision {
+    location: KeyLocation,
+    /* the key id the uid is locked to, None if it isn't locked yet */
+    locked_key_id: Option<String>,
 }
 
-fn write_lock_file(repo: &Repository, uid: &str, key_id: &str) {
-    let path = get_lock_dir(repo).join(uid);
-    fs::write(path, key_id).unwrap();
+/* Decides where to find the key of the uid without importing keys or writing lock files.
+ * Unlocked uids are looked up in the keyring, then on github. Locked uids are looked up by
+ * their key id in the keyring, then in the store, then on github */
+fn decide_name(repo: &Repository, backend: &dyn CryptoBackend, uid: &str) -> Result<Decision> {
+    let locked_key_id = get_locked_key_id(repo, uid)?;
+    let location = match locked_key_id {
+        None if backend.has_key(uid)? => KeyLocation::Keyring(backend.get_key_id(uid)?),
+        None => match github::get_key(uid, None)? {
+            Some(key) => KeyLocation::Import(KeySource::Github, key),
+            None => return Err(GitCredError::UnresolvableRecipient(uid.to_string())),
+        },
+        Some(ref key_id) if backend.has_key(key_id)? => KeyLocation::Keyring(key_id.clone()),
+        Some(ref key_id) => {
+            let key_path = get_key_path(repo, PUBLIC_KEY_FOLDER_NAME, key_id)?;
+            if key_path.is_file() {
+                KeyLocation::Import(KeySource::Store, fs::read_to_string(key_path)?)
+            } else {
+                match github::get_key(uid, Some(key_id.clone()))? {
+                    Some(key) => KeyLocation::Import(KeySource::Github, key),
+                    None => return Err(GitCredError::UnresolvableRecipient(format!("{} (locked to key id: {})", uid, key_id))),
+                }
+            }
+        }
+    };
+    Ok(Decision { location, locked_key_id })
 }
 
-fn get_locked_key_id(repo: &Repository, uid: &str) -> Option<String> {
-    let lock_dir = get_lock_dir(repo);
-    let file_path = lock_dir.join(uid);
-    if !file_path.exists() {
-        return None;
+/* Works out how resolve_age_name resolves the uid, without writing lock files.
+ * Returns the recipients and the lock file resolve_age_name creates for them */
+pub fn preview_age_name(repo: &Repository, uid: &str) -> Result<(Vec<String>, Option<PathBuf>)> {
+    if age_backend::is_recipient(uid) {
+        return Ok((vec![uid.to_string()], None));
     }
 
-    let bytes = fs::read(file_path).unwrap();
-    let contents = String::from_utf8(bytes).unwrap();
-    return Some(contents.trim().to_string());
-}
+    let lock_path = get_key_path(repo, AGE_LOCK_FOLDER_NAME, uid)?;
+    if lock_path.is_file() {
+        let contents = fs::read_to_string(lock_path)?;
+        return Ok((contents.lines().filter(|line| !line.trim().is_empty()).map(|line| line.to_string()).collect(), None));
+    }
 
-fn get_lock_dir(repo: &Repository) -> PathBuf {
-    let lock_path = git_utils::get_credentials_dir(&repo).join(KEY_FOLDER_NAME).join(LOCK_FOLDER_NAME);
-    if !lock_path.exists() {
-        fs::create_dir_all(&lock_path).unwrap();
+    let keys: Vec<String> = github::get_ssh_keys(uid)?
+        .into_iter()
+        .filter(|key| age_backend::is_recipient(key))
+        .collect();
+    if keys.is_empty() {
+        return Err(GitCredError::UnresolvableRecipient(uid.to_string()));
     }
-    return lock_path;
+    Ok((keys, Some(lock_path)))
 }
 
-fn get_saved_key(repo: &Repository, key_id: &str) -> Option<String> {
-    let key_path = 
-        git_utils::get_credentials_dir(repo)
-        .join(KEY_FOLDER_NAME)
-        .join(PUBLIC_KEY_FOLDER_NAME)
-        .join(key_id);
-    if !key_path.exists() || !key_path.is_file() {
-        return None;
+/* Returns the lock file save-key writes for the uid and the file it saves the key with the given key id to */
+pub fn get_save_key_paths(repo: &Repository, uid: &str, key_id: &str) -> Result<(PathBuf, PathBuf)> {
+    Ok((get_key_path(repo, LOCK_FOLDER_NAME, uid)?, get_key_path(repo, PUBLIC_KEY_FOLDER_NAME, key_id)?))
+}
+
+/* Resolves an age recipient. Age and ssh public keys are used as is, anything else
+ * is taken as a github username whose ssh keys are all used as recipients */
+pub fn resolve_age_name(repo: &Repository, uid: &str) -> Result<Vec<String>> {
+    let (keys, new_lock_file) = preview_age_name(repo, uid)?;
+    if let Some(lock_path) = new_lock_file {
+        eprintln!("Found ssh keys for user: {} in github", uid);
+        fs::create_dir_all(lock_path.parent().unwrap())?;
+        fs::write(lock_path, keys.join("\n") + "\n")?;
     }
+    Ok(keys)
+}
+
+pub fn set_key(repo: &Repository, uid: &str, pub_key: &str) -> Result<()> {
+    save_key(repo, pub_key, uid)
+}
 
-    let contents = fs::read_to_string(key_path);
-    if contents.is_err() {
-        return None;
+fn write_lock_file(repo: &Repository, uid: &str, key_id: &str) -> Result<()> {
+    let path = get_key_path(repo, LOCK_FOLDER_NAME, uid)?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, key_id)?;
+    Ok(())
+}
+
+/* Returns the key id the uid is locked to, if it is */
+fn get_locked_key_id(repo: &Repository, uid: &str) -> Result<Option<String>> {
+    let file_path = get_key_path(repo, LOCK_FOLDER_NAME, uid)?;
+    if !file_path.exists() {
+        return Ok(None);
     }
 
-    return Some(gpg::import_key(&contents.unwrap()));
+    let contents = fs::read_to_string(file_path)?;
+    return Ok(Some(contents.trim().to_string()));
 }
 
-fn save_key(repo: &Repository, pub_key: &str, uid: &str) {
-    let key_id = gpg::import_key(pub_key);
-    let path = git_utils::get_credentials_dir(repo)
+/* Returns the path of a file in the .keys folder of the store, without creating any folder */
+fn get_key_path(repo: &Repository, folder: &str, name: &str) -> Result<PathBuf> {
+    Ok(git_utils::get_credentials_location(repo)?.join(KEY_FOLDER_NAME).join(folder).join(name))
+}
+
+fn save_key(repo: &Repository, pub_key: &str, uid: &str) -> Result<()> {
+    let key_id = crypto::get_pgp_backend(repo)?.import_key(pub_key)?;
+    let path = git_utils::get_credentials_dir(repo)?
         .join(KEY_FOLDER_NAME)
         .join(PUBLIC_KEY_FOLDER_NAME);
-    fs::create_dir_all(path.clone()).unwrap();
+    fs::create_dir_all(path.clone())?;
 
     let key_path = path.join(key_id.clone());
-    fs::write(key_path, pub_key).unwrap();
-    write_lock_file(repo, uid, &key_id);
-}
\ No newline at end of file
+    fs::write(key_path, pub_key)?;
+    write_lock_file(repo, uid, &key_id)
+}
@@ -0,0 +1,307 @@
+use git2::Repository;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use decrypt;
+use dryrun;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+use gpg_id;
+use private_file;
+use reencrypt;
+use reencrypt::ReencryptOptions;
+use render;
+use crypto;
+use crypto::BackendKind;
+use resolver;
+use transfer;
+
+/// A secret or a folder in the store
+pub struct StoreEntry {
+    /// Path of the entry, relative to the root of the store
+    pub path: PathBuf,
+    pub is_folder: bool,
+}
+
+/// Handle to the credential store of a git repository.
+///
+/// All paths taken by a `Store` are relative to the root of the store,
+/// e.g. `foo/bar` refers to `.credential-store/foo/bar`.
+pub struct Store<'a> {
+    repo: &'a Repository,
+}
+
+impl<'a> Store<'a> {
+    /// Opens the credential store of `repo`, creating it if it doesn't exist yet
+    pub fn open(repo: &'a Repository) -> Result<Store<'a>> {
+        git_utils::get_credentials_dir(repo)?;
+        Ok(Store { repo })
+    }
+
+    /// Opens the credential store of `repo` without creating it. Anything that writes to
+    /// the store creates it
+    pub fn open_without_creating(repo: &'a Repository) -> Store<'a> {
+        Store { repo }
+    }
+
+    pub(crate) fn repo(&self) -> &Repository {
+        self.repo
+    }
+
+    /// Absolute path to the root of the store, which might not exist yet
+    pub fn path(&self) -> Result<PathBuf> {
+        git_utils::get_credentials_location(self.repo)
+    }
+
+    /// Sets the gpg ids (and optionally the backend, see `set_backend`) of `folder` (use an empty
+    /// path for the root folder) and reencrypts everything underneath it.
+    /// If `recipients` is empty, the user.email of the git config is used.
+    /// If anything fails, the folder is left as it was. Keys imported and lock files written
+    /// while resolving the gpg ids are kept.
+    pub fn init<P: AsRef<Path>>(&self, folder: P, recipients: Vec<String>, backend: Option<&str>, options: &ReencryptOptions) -> Result<()> {
+        if let Some(backend) = backend {
+            BackendKind::from_name(backend)?;
+        }
+        if options.dry_run {
+            return dryrun::init(self.repo, folder.as_ref(), recipients, backend, options);
+        }
+        reencrypt::init_folder(self.repo, folder.as_ref(), recipients, backend, options)
+    }
+
+    /// Sets the encryption backend (`gpg`, `age` or `native`) of `folder` and its subfolders.
+    /// Existing files are only switched over on the next reencrypt.
+    pub fn set_backend<P: AsRef<Path>>(&self, folder: P, backend: &str) -> Result<()> {
+        BackendKind::from_name(backend)?;
+        git_utils::write_backend_file(self.repo, folder.as_ref(), backend)
+    }
+
+    /// Normalizes the path of a secret given by the user, failing if it's outside of the store
+    /// or a dot-entry (such as `.gpg_id`)
+    pub fn check_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        transfer::check_store_path(path.as_ref(), false)
+    }
+
+    pub fn encrypt<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) -> Result<()> {
+        encrypt::encrypt_bytes(self.repo, &self.check_path(path)?, contents.as_ref())
+    }
+
+    /// Encrypts the contents of the file at `file_name` into `path` in the store
+    pub fn encrypt_file<P: AsRef<Path>>(&self, path: P, file_name: &str) -> Result<()> {
+        encrypt::encrypt_file(self.repo, &self.check_path(path)?, file_name)
+    }
+
+    pub fn decrypt<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        decrypt::decrypt(self.repo, &self.check_path(path)?)
+    }
+
+    /// Decrypts a secret into `output`, the file is only readable by the current user
+    pub fn decrypt_to_file<P: AsRef<Path>, O: AsRef<Path>>(&self, path: P, output: O) -> Result<()> {
+        let contents = self.decrypt(path)?;
+        private_file::write(output.as_ref(), &contents)
+    }
+
+    /// Decrypts a secret that is expected to be UTF-8 text
+    pub fn decrypt_string<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let contents = self.decrypt(path.as_ref())?;
+        String::from_utf8(contents).map_err(|err| GitCredError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+
+    /// Replaces every `{{ cred "path" }}` placeholder in `template` with the decrypted secret
+    /// at `path`. Fails without decrypting anything if a referenced secret doesn't exist.
+    pub fn render(&self, template: &str) -> Result<Vec<u8>> {
+        render::render(self.repo, template)
+    }
+
+    /// Renders `template` into `output`, the file is only readable by the current user
+    pub fn render_to_file<O: AsRef<Path>>(&self, template: &str, output: O) -> Result<()> {
+        let contents = self.render(template)?;
+        private_file::write(output.as_ref(), &contents)
+    }
+
+    /// Checks that every placeholder in `template` references a secret in the store, without decrypting
+    pub fn check_template(&self, template: &str) -> Result<()> {
+        render::check(self.repo, template)
+    }
+
+    /// Removes the secret (or folder and everything in it) at `path` from the store
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        transfer::remove(self.repo, path.as_ref())
+    }
+
+    /// Copies a secret or folder to `to` (or into `to` if it is an existing folder),
+    /// reencrypting the copies whose recipients differ at the destination.
+    /// Existing entries are only overwritten if `force` is set. Returns the path of the copy.
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q, force: bool) -> Result<PathBuf> {
+        transfer::copy(self.repo, from.as_ref(), to.as_ref(), force)
+    }
+
+    /// Moves a secret or folder, see `copy`
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q, force: bool) -> Result<PathBuf> {
+        transfer::rename(self.repo, from.as_ref(), to.as_ref(), force)
+    }
+
+    /// Reencrypts the whole store based on the gpg ids present in the store.
+    /// Unless `options.force` is set, files already encrypted for their recipients are left as is.
+    /// If anything fails, no file is changed. Keys imported and lock files written while
+    /// resolving the gpg ids are kept.
+    pub fn reencrypt(&self, options: &ReencryptOptions) -> Result<()> {
+        self.reencrypt_paths(&[""], options)
+    }
+
+    /// Like `reencrypt`, but only reencrypts the secrets at `paths` and under the folders in `paths`.
+    /// Files are still encrypted for the gpg ids they inherit from their parent folders.
+    pub fn reencrypt_paths<P: AsRef<Path>>(&self, paths: &[P], options: &ReencryptOptions) -> Result<()> {
+        let mut store_paths = Vec::new();
+        for path in paths {
+            store_paths.push(transfer::check_store_path(path.as_ref(), true)?);
+        }
+        if options.dry_run {
+            return dryrun::reencrypt(self.repo, &store_paths, options);
+        }
+        reencrypt::reencrypt_paths(self.repo, &store_paths, options)
+    }
+
+    /// Lists the paths of every secret in the store, skipping dot-entries
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        encrypt::list_folder(self.repo, &self.path()?)
+    }
+
+    /// Lists the paths of every secret under `folder`, skipping dot-entries
+    pub fn list_folder<P: AsRef<Path>>(&self, folder: P) -> Result<Vec<PathBuf>> {
+        let path = self.path()?.join(transfer::check_store_path(folder.as_ref(), true)?);
+        if !path.is_dir() {
+            return Err(GitCredError::Io(io::Error::new(io::ErrorKind::NotFound, format!("No such folder in credential store: {}", folder.as_ref().display()))));
+        }
+        encrypt::list_folder(self.repo, &path)
+    }
+
+    /// Lists the secrets and subfolders directly in `folder`, skipping dot-entries
+    pub fn entries<P: AsRef<Path>>(&self, folder: P) -> Result<Vec<StoreEntry>> {
+        let path = self.path()?.join(transfer::check_store_path(folder.as_ref(), true)?);
+        if !path.is_dir() {
+            return Err(GitCredError::Io(io::Error::new(io::ErrorKind::NotFound, format!("No such folder in credential store: {}", folder.as_ref().display()))));
+        }
+
+        let entries = encrypt::list_entries(self.repo, &path)?;
+        Ok(entries.into_iter().map(|(path, is_folder)| StoreEntry { path, is_folder }).collect())
+    }
+
+    /// Returns the gpg ids (as written in the `.gpg_id` files) that the file at `path` is encrypted for
+    pub fn recipients_for<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>> {
+        encrypt::get_gpg_ids_for_file(self.repo, path.as_ref())
+    }
+
+    /// Returns the gpg ids that files directly in `folder` are encrypted for
+    pub fn recipients_for_folder<P: AsRef<Path>>(&self, folder: P) -> Result<Vec<String>> {
+        encrypt::get_gpg_ids_for_folder(self.repo, folder.as_ref())
+    }
+
+    /// Returns the folder whose `.gpg_id` file gives the gpg ids of `folder`: `folder` itself,
+    /// or the closest parent with a `.gpg_id` file
+    pub fn recipients_source<P: AsRef<Path>>(&self, folder: P) -> Result<PathBuf> {
+        gpg_id::find_gpg_id_folder(self.repo, &transfer::check_store_path(folder.as_ref(), true)?)
+    }
+
+    /// Adds `recipients` to the `.gpg_id` file of `folder`, keeping its other lines and comments, and
+    /// reencrypts the folder. A folder without a `.gpg_id` file gets one with the gpg ids it inherited.
+    /// The new gpg ids are resolved first; if anything fails, the folder is left as it was.
+    /// Returns the gpg ids that weren't gpg ids of the folder already.
+    pub fn add_recipients<P: AsRef<Path>>(&self, folder: P, recipients: &[String], options: &ReencryptOptions) -> Result<Vec<String>> {
+        gpg_id::add_recipients(self.repo, &transfer::check_store_path(folder.as_ref(), true)?, recipients, options)
+    }
+
+    /// Removes `recipients` from the `.gpg_id` file of `folder`, keeping its other lines and comments,
+    /// and reencrypts the folder. If anything fails, the folder is left as it was.
+    pub fn remove_recipients<P: AsRef<Path>>(&self, folder: P, recipients: &[String], options: &ReencryptOptions) -> Result<()> {
+        gpg_id::remove_recipients(self.repo, &transfer::check_store_path(folder.as_ref(), true)?, recipients, options)
+    }
+
+    /// Stores the public key of `uid` in the store so other users can encrypt for it.
+    /// If `pub_key` is `None`, the key is looked up in the local keyring or on github.
+    pub fn save_key(&self, uid: &str, pub_key: Option<&str>) -> Result<()> {
+        match pub_key {
+            Some(key) => resolver::set_key(self.repo, uid, key),
+            None => {
+                let backend = crypto::get_pgp_backend(self.repo)?;
+                let key_id = resolver::resolve_name(self.repo, &*backend, uid)?;
+                let pub_key = backend.export_key(&key_id)?;
+                resolver::set_key(self.repo, uid, &pub_key)
+            }
+        }
+    }
+
+    /// Prints what `save_key` would do: the key id and fingerprint of the key, and the key and
+    /// lock files it would write, without touching the store or the keyring
+    pub fn save_key_dry_run(&self, uid: &str, pub_key: Option<&str>) -> Result<()> {
+        dryrun::save_key(self.repo, uid, pub_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use test_utils::TestRepo;
+    use super::*;
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        store.encrypt("db/password", "hunter2").unwrap();
+        assert_eq!(store.decrypt_string("db/password").unwrap(), "hunter2");
+        assert!(store.decrypt("db/user").is_err());
+    }
+
+    #[test]
+    fn only_writes_secrets_in_the_store() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        for path in &["../outside", "db/../../outside", "/tmp/outside", ".gpg_id", "db/.backend", ""] {
+            assert!(store.encrypt(path, "hunter2").is_err(), "{}", path);
+            assert!(store.decrypt(path).is_err(), "{}", path);
+        }
+        assert!(!test_repo.exists("outside"));
+        assert_eq!(fs::read_to_string(test_repo.store_dir().join(".gpg_id")).unwrap(), format!("{}\n", test_repo.recipient));
+    }
+
+    #[test]
+    fn lists_secrets() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        store.encrypt("api_key", "1").unwrap();
+        store.encrypt("db/password", "2").unwrap();
+        store.encrypt("db/prod/password", "3").unwrap();
+
+        assert_eq!(store.list().unwrap(), vec![PathBuf::from("api_key"), PathBuf::from("db/password"), PathBuf::from("db/prod/password")]);
+        assert_eq!(store.list_folder("db/prod").unwrap(), vec![PathBuf::from("db/prod/password")]);
+        let entries: Vec<(PathBuf, bool)> = store.entries("db").unwrap().into_iter().map(|entry| (entry.path, entry.is_folder)).collect();
+        assert_eq!(entries, vec![(PathBuf::from("db/password"), false), (PathBuf::from("db/prod"), true)]);
+    }
+
+    #[test]
+    fn only_lists_folders_in_the_store() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        for folder in &["..", "/etc", "db/../..", ".keys", "missing"] {
+            assert!(store.list_folder(folder).is_err(), "{}", folder);
+            assert!(store.entries(folder).is_err(), "{}", folder);
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let test_repo = TestRepo::new();
+        let store = test_repo.store();
+        store.encrypt("db/password", "hunter2").unwrap();
+
+        let rendered = store.render("password: {{ cred \"db/password\" }}\nname: {{ .Values.name }}\n").unwrap();
+        assert_eq!(rendered, b"password: hunter2\nname: {{ .Values.name }}\n");
+        match store.render("{{ cred \"db/password\" }} {{ cred \"db/user\" }}\n{{ cred \"api_key\" }}") {
+            Err(GitCredError::Template(message)) => assert_eq!(message, "line 1: no such secret in credential store: db/user\n\
+                line 2: no such secret in credential store: api_key"),
+            _ => panic!("rendered a template with missing secrets"),
+        }
+    }
+}
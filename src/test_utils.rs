@@ -0,0 +1,64 @@
+/* Scratch repos for the unit tests. Their store uses the age backend with a generated identity,
+ * so the tests don't depend on a gpg setup or on the network */
+
+use age;
+use age::secrecy::ExposeSecret;
+use git2::Repository;
+use std::fs;
+use std::path::PathBuf;
+use private_file::PrivateDir;
+use store::Store;
+
+pub struct TestRepo {
+    pub repo: Repository,
+    pub recipient: String,
+    dir: PrivateDir,
+}
+
+impl TestRepo {
+    /* A repo with an initialized store, encrypted for `recipient` */
+    pub fn new() -> TestRepo {
+        let test_repo = TestRepo::without_store();
+        fs::create_dir(test_repo.store_dir()).unwrap();
+        fs::write(test_repo.store_dir().join(".gpg_id"), format!("{}\n", test_repo.recipient)).unwrap();
+        test_repo
+    }
+
+    pub fn without_store() -> TestRepo {
+        let dir = PrivateDir::new().unwrap();
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+
+        let identity = age::x25519::Identity::generate();
+        let identity_path = dir.write_file("identity", identity.to_string().expose_secret().as_bytes()).unwrap();
+        {
+            let mut config = repo.config().unwrap().open_level(git2::ConfigLevel::Local).unwrap();
+            config.set_str("creds.backend", "age").unwrap();
+            config.set_str("creds.identity", &identity_path.to_string_lossy()).unwrap();
+            config.set_str("user.email", "test@example.com").unwrap();
+        }
+
+        let recipient = identity.to_public().to_string();
+        TestRepo { repo, recipient, dir }
+    }
+
+    pub fn store<'a>(&'a self) -> Store<'a> {
+        Store::open_without_creating(&self.repo)
+    }
+
+    pub fn top_level(&self) -> PathBuf {
+        self.dir.path().join("repo")
+    }
+
+    pub fn store_dir(&self) -> PathBuf {
+        self.top_level().join(".credential-store")
+    }
+
+    /* Writes a file outside of the repo, e.g. a version of a file handed to the merge driver */
+    pub fn write_file(&self, name: &str, contents: &[u8]) -> PathBuf {
+        self.dir.write_file(name, contents).unwrap()
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.top_level().join(path).exists()
+    }
+}
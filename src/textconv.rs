@@ -0,0 +1,71 @@
+use git2::ConfigLevel;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crypto;
+use error::Result;
+use git_utils;
+use private_file;
+use store::Store;
+
+const TEXTCONV_KEY_NAME: &'static str = "diff.cred.textconv";
+const REDACT_KEY_FILE_NAME: &'static str = "cred-redact-key";
+const UNREADABLE_MESSAGE: &'static [u8] = b"[git-cred: unable to decrypt]\n";
+
+/// Git textconv driver: returns the plaintext of the encrypted file at `path` so diffs of the
+/// store are readable. Files that can't be decrypted show up as a single placeholder line.
+///
+/// With `redact`, every line is replaced with a keyed hash of it, so diffs only show which lines
+/// changed. The key is random and kept in the repo's git directory, so the hashes can't be
+/// compared against guesses by anyone else.
+pub fn textconv(store: &Store, path: &Path, redact: bool) -> Result<Vec<u8>> {
+    let plaintext = match crypto::get_backend_for_ciphertext(store.repo(), path)?.decrypt(path) {
+        Ok(plaintext) => plaintext,
+        Err(_) => return Ok(UNREADABLE_MESSAGE.to_vec()),
+    };
+    if !redact {
+        return Ok(plaintext);
+    }
+
+    let key = get_redact_key(store)?;
+    let mut redacted = String::new();
+    let trimmed = plaintext.strip_suffix(b"\n").unwrap_or(&plaintext);
+    for line in trimmed.split(|&byte| byte == b'\n') {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+        mac.update(line);
+        let hash = mac.finalize().into_bytes();
+        let hex: String = hash[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+        redacted += &format!("[redacted {}]\n", hex);
+    }
+    Ok(redacted.into_bytes())
+}
+
+/// Registers the textconv driver in the repo's git config and marks the secrets of the store
+/// (but not its dot-files such as `.gpg_id`) as using it in `.gitattributes`.
+/// An existing driver is only replaced to switch to redacted diffs, never back to plaintext ones.
+pub fn install(store: &Store, redact: bool) -> Result<()> {
+    let repo = store.repo();
+    let mut config = repo.config()?.open_level(ConfigLevel::Local)?;
+    if redact || config.get_string(TEXTCONV_KEY_NAME).is_err() {
+        let command = if redact { "git cred textconv --redact" } else { "git cred textconv" };
+        config.set_str(TEXTCONV_KEY_NAME, command)?;
+    }
+    git_utils::add_store_attributes(repo, "diff")
+}
+
+/* Creates the key on first use */
+fn get_redact_key(store: &Store) -> Result<Vec<u8>> {
+    let path = store.repo().path().join(REDACT_KEY_FILE_NAME);
+    if path.is_file() {
+        return Ok(fs::read(path)?);
+    }
+
+    let mut key = vec![0; 32];
+    File::open("/dev/urandom")?.read_exact(&mut key)?;
+    private_file::write(&path, &key)?;
+    Ok(key)
+}
@@ -0,0 +1,218 @@
+use git2::Repository;
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use crypto::BackendKind;
+use encrypt;
+use error::GitCredError;
+use error::Result;
+use git_utils;
+
+/* Copies a secret or folder (with its .gpg_id files) within the store, and reencrypts
+ * every copied file whose effective recipients differ at the destination.
+ * If the destination is an existing folder, the source is copied into it.
+ * Returns the path of the copy */
+pub fn copy(repo: &Repository, from: &Path, to: &Path, force: bool) -> Result<PathBuf> {
+    let credentials_dir = git_utils::get_credentials_dir(repo)?;
+    let from = check_store_path(from, false)?;
+    let to = check_store_path(to, true)?;
+
+    let source = credentials_dir.join(&from);
+    if !source.exists() {
+        return Err(io_error(io::ErrorKind::NotFound, format!("No such file or folder in credential store: {}", from.display())));
+    }
+
+    let mut destination_path = to.clone();
+    if credentials_dir.join(&to).is_dir() {
+        destination_path = destination_path.join(from.file_name().unwrap());
+    }
+    if destination_path.starts_with(&from) {
+        return Err(io_error(io::ErrorKind::InvalidInput, format!("Unable to copy {} into itself", from.display())));
+    }
+
+    let destination = credentials_dir.join(&destination_path);
+    if destination.exists() && !force {
+        return Err(io_error(io::ErrorKind::AlreadyExists, format!("{} already exists in credential store, use --force to overwrite it", destination_path.display())));
+    }
+
+    /* the copy is made next to the destination, so that it inherits the same gpg ids, and only
+     * replaces an existing destination once it was entirely reencrypted */
+    let staged_path = sibling_path(&destination_path, "copy");
+    let staged = credentials_dir.join(&staged_path);
+    fs::create_dir_all(staged.parent().unwrap())?;
+    let mut result = copy_recursive(&source, &staged);
+    if result.is_ok() {
+        result = reencrypt_changed(repo, &from, &staged_path, &destination_path);
+    }
+    if result.is_ok() {
+        result = replace(&staged, &credentials_dir.join(sibling_path(&destination_path, "old")), &destination);
+    }
+    if result.is_err() && staged.exists() {
+        remove_path(&staged)?;
+    }
+    result.map(|_| destination_path)
+}
+
+/* Moves `staged` to `destination`, first moving an existing destination out of the way to `backup`
+ * so that it can be put back if the move fails */
+fn replace(staged: &Path, backup: &Path, destination: &Path) -> Result<()> {
+    let replacing = destination.exists();
+    if replacing {
+        fs::rename(destination, backup)?;
+    }
+    if let Err(err) = fs::rename(staged, destination) {
+        if replacing {
+            fs::rename(backup, destination)?;
+        }
+        return Err(err.into());
+    }
+    if replacing {
+        remove_path(backup)?;
+    }
+    Ok(())
+}
+
+/* A hidden path next to `path`, ignored when listing the store */
+fn sibling_path(path: &Path, purpose: &str) -> PathBuf {
+    let name = format!(".{}.cred-{}-{}", path.file_name().unwrap().to_string_lossy(), purpose, process::id());
+    path.with_file_name(name)
+}
+
+/* Like copy, but removes the source once the copy succeeded */
+pub fn rename(repo: &Repository, from: &Path, to: &Path, force: bool) -> Result<PathBuf> {
+    let destination_path = copy(repo, from, to, force)?;
+    let source = git_utils::get_credentials_dir(repo)?.join(check_store_path(from, false)?);
+    remove_path(&source)?;
+    Ok(destination_path)
+}
+
+/* Removes a secret, or a folder and everything in it */
+pub fn remove(repo: &Repository, path: &Path) -> Result<()> {
+    let path = check_store_path(path, false)?;
+    let full_path = git_utils::get_credentials_location(repo)?.join(&path);
+    if !full_path.exists() {
+        return Err(io_error(io::ErrorKind::NotFound, format!("No such file or folder in credential store: {}", path.display())));
+    }
+    remove_path(&full_path)
+}
+
+/* Reencrypts the files of the copy at `staged`, which is going to be moved to `to`, whose recipients
+ * differ from the ones of the source */
+fn reencrypt_changed(repo: &Repository, from: &Path, staged: &Path, to: &Path) -> Result<()> {
+    let credentials_dir = git_utils::get_credentials_dir(repo)?;
+    let copy = credentials_dir.join(staged);
+    let copied_files = if copy.is_dir() {
+        encrypt::list_folder(repo, &copy)?
+    } else {
+        vec![staged.to_path_buf()]
+    };
+
+    for copied_file in copied_files {
+        let relative_path = copied_file.strip_prefix(staged).unwrap();
+        let (source_file, destination_file) = if relative_path.as_os_str().is_empty() {
+            (from.to_path_buf(), to.to_path_buf())
+        } else {
+            (from.join(relative_path), to.join(relative_path))
+        };
+
+        if encryption_target(repo, &source_file)? != encryption_target(repo, &copied_file)? {
+            println!("reencrypting file: {}", destination_file.display());
+            encrypt::reencrypt_file(repo, &copied_file)?;
+        }
+    }
+    Ok(())
+}
+
+/* The backend and gpg ids a file is encrypted with, files with the same target can be copied as is */
+fn encryption_target(repo: &Repository, path: &Path) -> Result<(BackendKind, Vec<String>)> {
+    let backend_kind = encrypt::get_backend_kind_for_file(repo, path)?;
+    let mut gpg_ids = encrypt::get_gpg_ids_for_file(repo, path)?;
+    gpg_ids.sort();
+    gpg_ids.dedup();
+    Ok((backend_kind, gpg_ids))
+}
+
+/* Normalizes a path given by the user, refusing paths that leave the store or touch dot-entries */
+pub fn check_store_path(path: &Path, allow_root: bool) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(name) if !name.to_string_lossy().starts_with(".") => normalized.push(name),
+            _ => return Err(io_error(io::ErrorKind::InvalidInput, format!("Invalid path in credential store: {}", path.display()))),
+        }
+    }
+
+    if !allow_root && normalized.as_os_str().is_empty() {
+        return Err(io_error(io::ErrorKind::InvalidInput, "Please provide a path inside the credential store".to_string()));
+    }
+    Ok(normalized)
+}
+
+fn copy_recursive(source: &Path, destination: &Path) -> Result<()> {
+    if !source.is_dir() {
+        fs::copy(source, destination)?;
+        return Ok(());
+    }
+
+    fs::create_dir(destination)?;
+    for file in fs::read_dir(source)? {
+        let file_res = file?;
+        copy_recursive(&file_res.path(), &destination.join(file_res.file_name()))?;
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn io_error(kind: io::ErrorKind, message: String) -> GitCredError {
+    GitCredError::Io(io::Error::new(kind, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(path: &str, allow_root: bool) -> Option<PathBuf> {
+        check_store_path(Path::new(path), allow_root).ok()
+    }
+
+    #[test]
+    fn normalizes_store_paths() {
+        assert_eq!(check("db/password", false), Some(PathBuf::from("db/password")));
+        assert_eq!(check("./db/./password/", false), Some(PathBuf::from("db/password")));
+        assert_eq!(check("db", true), Some(PathBuf::from("db")));
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_store() {
+        for path in &["..", "../x", "db/../../x", "db/../x", "/etc/passwd", "/"] {
+            assert_eq!(check(path, true), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_dot_entries() {
+        for path in &[".gpg_id", "db/.gpg_id", ".keys/locks/x", ".git", "db/.backend"] {
+            assert_eq!(check(path, true), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn only_allows_the_root_when_asked() {
+        assert_eq!(check("", true), Some(PathBuf::new()));
+        assert_eq!(check(".", true), Some(PathBuf::new()));
+        assert_eq!(check("", false), None);
+        assert_eq!(check("./", false), None);
+    }
+}
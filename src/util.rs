@@ -1,4 +0,0 @@
-pub fn error_out(message: &str) -> ! {
-    eprintln!("{}", message);
-    std::process::exit(2)
-}